@@ -0,0 +1,302 @@
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::connect6_ai::{self, Connect6Config};
+use crate::game::{Game, GamePhase, GameType, Move, Player};
+use crate::gomoku_ai::{self, GomokuConfig};
+use crate::mcts::{self, MctsConfig};
+use crate::opening::{OpeningChoice, OpeningRule, OpeningStep};
+
+// How often a running search reports how it is getting on
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+// How deep to search when judging a position during an opening protocol
+const OPENING_DEPTH: u32 = 3;
+
+// Snapshot of a search in progress, sent to the UI while the computer thinks
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Progress {
+    pub best_move: Option<Move>,
+    pub nodes: u64,
+    // The engine's estimate of the side to move's chance of winning, 0 to 1
+    pub win_rate: f32,
+}
+
+// Rough chance of winning for the side with this score in the five-in-a-row
+// engines: 1 or 0 once `win` is in sight, and otherwise about three times as
+// likely as the other side for every `four` points ahead, the worth of a four
+pub fn win_rate(score: i32, win: i32, four: i32) -> f32 {
+    if score >= win - 100 {
+        1.0
+    } else if score <= -(win - 100) {
+        0.0
+    } else {
+        1.0 / (1.0 + 3f32.powf(-(score as f32) / four as f32))
+    }
+}
+
+// Shared with the commands that can interrupt a running search
+#[derive(Default)]
+pub struct SearchControl {
+    // Ends the search early; the engine returns the best move found so far
+    pub stop: AtomicBool,
+    // Set together with `stop` when that move should be thrown away
+    pub cancelled: AtomicBool,
+}
+
+// Named playing strengths, chosen with the game settings
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum Difficulty {
+    Beginner,
+    Easy,
+    #[default]
+    Medium,
+    Hard,
+    Strong,
+}
+
+// What a difficulty level means for each engine
+#[derive(Clone, Copy, Debug)]
+pub struct Strength {
+    pub go: MctsConfig,
+    pub gomoku: GomokuConfig,
+    pub connect6: Connect6Config,
+    // Chance of deliberately playing one of the weaker candidate moves
+    pub mistake_rate: f64,
+    // Relative randomness in how the Go engine ranks its moves by visits
+    pub go_noise: f32,
+}
+
+// Go search budget set by hand over the one the difficulty picks; a field
+// left as None keeps the difficulty's value
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct AiConfig {
+    pub playouts: Option<usize>,
+    pub time_limit_ms: Option<u64>,
+}
+
+impl AiConfig {
+    pub fn apply(&self, mut strength: Strength) -> Strength {
+        if let Some(playouts) = self.playouts {
+            strength.go.playouts = playouts;
+        }
+        if let Some(ms) = self.time_limit_ms {
+            strength.go.time_limit_ms = Some(ms);
+        }
+        strength
+    }
+}
+
+impl Difficulty {
+    pub fn from_name(name: &str) -> Option<Difficulty> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "beginner" => Some(Difficulty::Beginner),
+            "easy" => Some(Difficulty::Easy),
+            "medium" => Some(Difficulty::Medium),
+            "hard" => Some(Difficulty::Hard),
+            "strong" => Some(Difficulty::Strong),
+            _ => None,
+        }
+    }
+
+    pub fn strength(&self) -> Strength {
+        match self {
+            Difficulty::Beginner => Strength {
+                go: MctsConfig { playouts: 100, time_limit_ms: Some(1000) },
+                gomoku: GomokuConfig { max_depth: 1, time_limit_ms: Some(500), threat_search: false, noise: 400 },
+                connect6: Connect6Config { candidates: 6, lookahead: false, noise: 300 },
+                mistake_rate: 0.3,
+                go_noise: 0.5,
+            },
+            Difficulty::Easy => Strength {
+                go: MctsConfig { playouts: 500, time_limit_ms: Some(2000) },
+                gomoku: GomokuConfig { max_depth: 2, time_limit_ms: Some(1000), threat_search: false, noise: 150 },
+                connect6: Connect6Config { candidates: 8, lookahead: false, noise: 100 },
+                mistake_rate: 0.15,
+                go_noise: 0.3,
+            },
+            Difficulty::Medium => Strength {
+                go: MctsConfig { playouts: 2000, time_limit_ms: Some(4000) },
+                gomoku: GomokuConfig { max_depth: 4, time_limit_ms: Some(2000), threat_search: true, noise: 40 },
+                connect6: Connect6Config { candidates: 10, lookahead: true, noise: 30 },
+                mistake_rate: 0.05,
+                go_noise: 0.1,
+            },
+            Difficulty::Hard => Strength {
+                go: MctsConfig { playouts: 6000, time_limit_ms: Some(8000) },
+                gomoku: GomokuConfig { max_depth: 8, time_limit_ms: Some(3000), threat_search: true, noise: 0 },
+                connect6: Connect6Config { candidates: 14, lookahead: true, noise: 0 },
+                mistake_rate: 0.0,
+                go_noise: 0.0,
+            },
+            Difficulty::Strong => Strength {
+                go: MctsConfig { playouts: 20000, time_limit_ms: Some(15000) },
+                gomoku: GomokuConfig { max_depth: 16, time_limit_ms: Some(6000), threat_search: true, noise: 0 },
+                connect6: Connect6Config { candidates: 20, lookahead: true, noise: 0 },
+                mistake_rate: 0.0,
+                go_noise: 0.0,
+            },
+        }
+    }
+}
+
+// Picks the computer's move at the given strength, usually the game's
+// difficulty. The search ends early once `stop` is set and reports its
+// progress now and then.
+pub fn choose_move(game: &Game, strength: &Strength, stop: &AtomicBool, report: &mut dyn FnMut(Progress)) -> Option<Move> {
+    let mut rng = rand::thread_rng();
+    let mistake = rng.gen_bool(strength.mistake_rate);
+
+    match game.game_type {
+        // In Atari Go the playouts end at the first capture, which makes the
+        // search go for captures and keep its own groups out of atari
+        GameType::Go(_) | GameType::AtariGo if game.phase == GamePhase::Playing => {
+            let stats = mcts::search(game, &strength.go, stop, report);
+            if stats.is_empty() {
+                return game.game_type.is_go().then_some(Move::Pass);
+            }
+            // A mistake is one of the next few moves the search looked at
+            let weaker = &stats[1..stats.len().min(6)];
+            if mistake && !weaker.is_empty() {
+                return Some(weaker[rng.gen_range(0..weaker.len())].mv);
+            }
+            let noisy = |visits: u32, rng: &mut rand::rngs::ThreadRng| {
+                visits as f32 * (1.0 + strength.go_noise * rng.gen_range(-1.0..=1.0))
+            };
+            let mut best = stats[0].mv;
+            let mut best_value = f32::MIN;
+            for s in &stats {
+                let value = noisy(s.visits, &mut rng);
+                if value > best_value {
+                    best_value = value;
+                    best = s.mv;
+                }
+            }
+            Some(best)
+        }
+        GameType::Gomoku(_) | GameType::Renju | GameType::Pente if mistake => {
+            // Move ordering still insists on blocking a four, so mistakes stay plausible
+            let moves = gomoku_ai::ranked_moves(game, 6);
+            if moves.is_empty() {
+                return None;
+            }
+            let (x, y) = moves[rng.gen_range(0..moves.len())];
+            Some(Move::Place(x, y))
+        }
+        GameType::Gomoku(_) | GameType::Renju | GameType::Pente => gomoku_ai::best_move(game, &strength.gomoku, stop, report).map(|(x, y)| Move::Place(x, y)),
+        // The first stone of the turn; the second is chosen once it is down
+        GameType::Connect6 => choose_turn(game, strength, stop, report)?.first().copied(),
+        // Free handicap placement and other set-up phases
+        GameType::Go(_) | GameType::AtariGo => game.get_ai_move().map(|(x, y)| Move::Place(x, y)),
+    }
+}
+
+// All moves of the computer's turn, which in Connect6 is usually two stones
+pub fn choose_turn(game: &Game, strength: &Strength, stop: &AtomicBool, report: &mut dyn FnMut(Progress)) -> Option<Vec<Move>> {
+    if game.game_type != GameType::Connect6 {
+        return choose_move(game, strength, stop, report).map(|mv| vec![mv]);
+    }
+    let mut rng = rand::thread_rng();
+    let turn = if rng.gen_bool(strength.mistake_rate) {
+        let turns = connect6_ai::ranked_turns(game, 6);
+        turns.get(rng.gen_range(0..turns.len().max(1))).cloned()?
+    } else {
+        connect6_ai::best_turn(game, &strength.connect6, strength.gomoku.time_limit_ms, stop, report)?
+    };
+    Some(turn.into_iter().map(|(x, y)| Move::Place(x, y)).collect())
+}
+
+// The computer's turn in a Gomoku opening protocol
+#[derive(Clone, Copy, Debug)]
+pub enum OpeningAction {
+    // A stone, an offered fifth move or the pick among them, as a click would be
+    Place(usize, usize),
+    Choose(OpeningChoice),
+}
+
+pub fn choose_opening_action(game: &Game, stop: &AtomicBool) -> Option<OpeningAction> {
+    let opening = game.opening.as_ref()?;
+    let stones = game.moves.len();
+    let black_after = |x: usize, y: usize, depth: u32| {
+        let mut after = game.clone();
+        after.play(x, y).ok()?;
+        Some(gomoku_ai::black_win_rate(&after, depth))
+    };
+
+    let action = match opening.step? {
+        OpeningStep::Place => {
+            let center = game.size / 2;
+            let max = opening.max_distance(stones);
+            let allowed = |&(x, y): &(usize, usize)| {
+                game.board[y][x] == Player::None
+                    && max.is_none_or(|max| x.abs_diff(center).max(y.abs_diff(center)) <= max)
+            };
+            if stones == 0 {
+                return Some(OpeningAction::Place(center, center));
+            }
+            let mut moves: Vec<(usize, usize)> = gomoku_ai::ranked_moves(game, 40).into_iter().filter(allowed).collect();
+            if moves.is_empty() {
+                moves = (0..game.size * game.size).map(|i| (i % game.size, i / game.size)).filter(allowed).collect();
+            }
+            // Stones for both colours are meant to leave an even position
+            let proposing = matches!(opening.rule, OpeningRule::Swap | OpeningRule::Swap2)
+                || (opening.rule == OpeningRule::Soosorv8 && stones < 3);
+            if proposing {
+                let mut rng = rand::thread_rng();
+                moves.truncate(12);
+                let (x, y) = moves
+                    .into_iter()
+                    .filter_map(|(x, y)| {
+                        let imbalance = (black_after(x, y, 0)? - 0.5).abs() + rng.gen_range(0.0..0.02);
+                        Some((imbalance, (x, y)))
+                    })
+                    .min_by(|a, b| a.0.total_cmp(&b.0))?
+                    .1;
+                OpeningAction::Place(x, y)
+            } else if max.is_none() {
+                let strength = game.difficulty.strength();
+                let (x, y) = gomoku_ai::best_move(game, &strength.gomoku, stop, &mut |_| {})?;
+                OpeningAction::Place(x, y)
+            } else {
+                let (x, y) = *moves.first()?;
+                OpeningAction::Place(x, y)
+            }
+        }
+        OpeningStep::Choose => {
+            let black = gomoku_ai::black_win_rate(game, OPENING_DEPTH);
+            let choices = opening.choices(stones);
+            if choices.contains(&OpeningChoice::PlaceTwo) && (black - 0.5).abs() < 0.1 {
+                OpeningAction::Choose(OpeningChoice::PlaceTwo)
+            } else if black >= 0.5 {
+                OpeningAction::Choose(OpeningChoice::Black)
+            } else {
+                OpeningAction::Choose(OpeningChoice::White)
+            }
+        }
+        OpeningStep::DeclareOffers => {
+            // The better Black stands, the more fifth moves White wants to choose from
+            let black = gomoku_ai::black_win_rate(game, OPENING_DEPTH);
+            OpeningAction::Choose(OpeningChoice::Offers(((black * 10.0).round() as usize).clamp(1, 8)))
+        }
+        OpeningStep::Offer => {
+            // White will take the weakest offer, so offer the strongest moves
+            let (x, y) = gomoku_ai::ranked_moves(game, opening.offer_count + opening.offers.len())
+                .into_iter()
+                .find(|p| !opening.offers.contains(p))?;
+            OpeningAction::Place(x, y)
+        }
+        OpeningStep::Pick => {
+            let (x, y) = opening
+                .offers
+                .iter()
+                .filter_map(|&(x, y)| Some((black_after(x, y, OPENING_DEPTH - 1)?, (x, y))))
+                .min_by(|a, b| a.0.total_cmp(&b.0))?
+                .1;
+            OpeningAction::Place(x, y)
+        }
+    };
+    Some(action)
+}
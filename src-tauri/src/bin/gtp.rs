@@ -0,0 +1,46 @@
+// Headless Go engine speaking GTP on stdin/stdout:
+//   gtp [--rules chinese|japanese|aga|nz|tromp-taylor] [--difficulty beginner|easy|medium|hard|strong]
+
+use std::io;
+use std::process::ExitCode;
+
+use go_game::ai::Difficulty;
+use go_game::game::{GameSettings, GameType};
+use go_game::gtp::GtpServer;
+use go_game::rules::Rules;
+
+fn main() -> ExitCode {
+    let mut rules = Rules::Chinese;
+    let mut difficulty = Difficulty::default();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_default();
+        let parsed = match arg.as_str() {
+            "--rules" => Rules::from_name(&value).map(|r| rules = r),
+            "--difficulty" => Difficulty::from_name(&value).map(|d| difficulty = d),
+            _ => None,
+        };
+        if parsed.is_none() {
+            eprintln!("Usage: gtp [--rules chinese|japanese|aga|nz|tromp-taylor] [--difficulty beginner|easy|medium|hard|strong]");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let settings = GameSettings {
+        size: 19,
+        game_type: GameType::Go(rules),
+        komi: None,
+        handicap: None,
+        difficulty,
+        opening: None,
+    };
+    let mut server = GtpServer::new(settings).expect("a game without handicap is always valid");
+    match server.run(io::stdin().lock(), io::stdout().lock()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("gtp: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
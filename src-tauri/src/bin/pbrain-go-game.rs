@@ -0,0 +1,34 @@
+// The built-in Gomoku engine as a Gomocup brain speaking the Piskvork protocol
+// on stdin/stdout. Tournament managers expect the "pbrain-" prefix in the name.
+//   pbrain-go-game [--difficulty beginner|easy|medium|hard|strong]
+
+use std::io;
+use std::process::ExitCode;
+
+use go_game::ai::Difficulty;
+use go_game::piskvork::PiskvorkBrain;
+
+fn main() -> ExitCode {
+    let mut difficulty = Difficulty::Strong;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_default();
+        match (arg.as_str(), Difficulty::from_name(&value)) {
+            ("--difficulty", Some(d)) => difficulty = d,
+            _ => {
+                eprintln!("Usage: pbrain-go-game [--difficulty beginner|easy|medium|hard|strong]");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let mut brain = PiskvorkBrain::new(difficulty);
+    match brain.run(io::stdin().lock(), io::stdout().lock()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("pbrain-go-game: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
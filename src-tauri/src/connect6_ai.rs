@@ -0,0 +1,331 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::ai::{self, Progress};
+use crate::game::{Game, Move, Player};
+
+const WIN: i32 = 1_000_000;
+// Value of a six-point window holding n stones of one colour and none of the
+// other; from four on it is a threat that needs a stone to block
+const WINDOW_SCORES: [i32; 7] = [0, 1, 10, 70, 600, 700, WIN];
+const DIRECTIONS: [(isize, isize); 4] = [(1, 0), (0, 1), (1, 1), (1, -1)];
+// Single points the opponent's replies are built from when looking ahead
+const REPLY_CANDIDATES: usize = 10;
+
+// How widely the Connect6 engine looks
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Connect6Config {
+    // Single points that the stones of a turn are paired from
+    pub candidates: usize,
+    // Judge each turn by the opponent's best reply rather than the position it leaves
+    pub lookahead: bool,
+    // Random amount, in evaluation points, added to each turn's score
+    pub noise: i32,
+}
+
+impl Default for Connect6Config {
+    fn default() -> Self {
+        Connect6Config {
+            candidates: 14,
+            lookahead: true,
+            noise: 0,
+        }
+    }
+}
+
+// Picks the stones for the player to move, one on Black's first turn and two
+// afterwards: a win if there is one, otherwise the pair that leaves the
+// opponent worst off, blocking every threat that has to be blocked. Once a
+// turn has been judged, the search ends at the time limit or when `stop` is
+// set, and it reports its progress after every turn it looks at.
+pub fn best_turn(
+    game: &Game,
+    config: &Connect6Config,
+    time_limit_ms: Option<u64>,
+    stop: &AtomicBool,
+    report: &mut dyn FnMut(Progress),
+) -> Option<Vec<(usize, usize)>> {
+    let mut position = Position::new(game);
+    let me = game.current_turn;
+    let stones = game.stones_to_place();
+    let mut rng = rand::thread_rng();
+    let deadline = time_limit_ms.map(|ms| Instant::now() + Duration::from_millis(ms));
+    let out_of_time = || stop.load(Ordering::Relaxed) || deadline.is_some_and(|d| Instant::now() >= d);
+    let mut best: Option<(i32, Vec<usize>)> = None;
+    for turn in position.turns(me, stones, config.candidates) {
+        let may_stop = best.is_some();
+        if may_stop && out_of_time() {
+            break;
+        }
+        for &p in &turn {
+            position.board[p] = me;
+        }
+        let score = if config.lookahead {
+            position.reply_value(me.other(), &|| may_stop && out_of_time())
+        } else {
+            Some(-position.evaluate(me.other()))
+        };
+        for &p in &turn {
+            position.board[p] = Player::None;
+        }
+        // A turn whose replies were not all looked at is left out
+        let Some(mut score) = score else {
+            break;
+        };
+        if config.noise > 0 && score.abs() < WIN - 100 {
+            score += rng.gen_range(0..=config.noise);
+        }
+        if best.as_ref().is_none_or(|(best_score, _)| score > *best_score) {
+            best = Some((score, turn));
+        }
+        if let Some((score, turn)) = &best {
+            report(Progress {
+                best_move: Some(Move::Place(turn[0] % game.size, turn[0] / game.size)),
+                nodes: position.nodes,
+                win_rate: ai::win_rate(*score, WIN, WINDOW_SCORES[4]),
+            });
+        }
+    }
+
+    let (_, turn) = best?;
+    Some(turn.iter().map(|&p| (p % game.size, p / game.size)).collect())
+}
+
+// The most promising turns by the position each leaves, best first
+pub fn ranked_turns(game: &Game, limit: usize) -> Vec<Vec<(usize, usize)>> {
+    let mut position = Position::new(game);
+    let me = game.current_turn;
+    let mut scored: Vec<(i32, Vec<usize>)> = position
+        .turns(me, game.stones_to_place(), Connect6Config::default().candidates)
+        .into_iter()
+        .map(|turn| {
+            for &p in &turn {
+                position.board[p] = me;
+            }
+            let score = -position.evaluate(me.other());
+            for &p in &turn {
+                position.board[p] = Player::None;
+            }
+            (score, turn)
+        })
+        .collect();
+    scored.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+    scored
+        .into_iter()
+        .take(limit)
+        .map(|(_, turn)| turn.iter().map(|&p| (p % game.size, p / game.size)).collect())
+        .collect()
+}
+
+struct Position {
+    size: usize,
+    board: Vec<Player>,
+    // Every line of six points, and which of them go through each point
+    windows: Vec<[usize; 6]>,
+    through: Vec<Vec<usize>>,
+    nodes: u64,
+}
+
+impl Position {
+    fn new(game: &Game) -> Self {
+        let size = game.size;
+        let mut windows = Vec::new();
+        let mut through = vec![Vec::new(); size * size];
+        for i in 0..size * size {
+            for d in DIRECTIONS {
+                let points: Option<Vec<usize>> = (0..6).map(|k| offset(size, i, d, k)).collect();
+                if let Some(points) = points {
+                    for &p in &points {
+                        through[p].push(windows.len());
+                    }
+                    windows.push([points[0], points[1], points[2], points[3], points[4], points[5]]);
+                }
+            }
+        }
+        Position {
+            size,
+            board: game.board.concat(),
+            windows,
+            through,
+            nodes: 0,
+        }
+    }
+
+    // Stones of `player` in window `w`, or None once the opponent has one there
+    fn count(&self, w: usize, player: Player) -> Option<usize> {
+        let mut count = 0;
+        for &p in &self.windows[w] {
+            match self.board[p] {
+                Player::None => {}
+                c if c == player => count += 1,
+                _ => return None,
+            }
+        }
+        Some(count)
+    }
+
+    // Windows where `player` could make six with the two stones of one turn
+    fn threats(&self, player: Player) -> Vec<usize> {
+        (0..self.windows.len())
+            .filter(|&w| self.count(w, player).is_some_and(|n| n >= 4))
+            .collect()
+    }
+
+    fn empties(&self, w: usize) -> impl Iterator<Item = usize> + '_ {
+        self.windows[w].iter().copied().filter(|&p| self.board[p] == Player::None)
+    }
+
+    // Fewest stones that put one in each of `windows`; 3 stands for more than a turn holds
+    fn stones_to_block(&self, windows: &[usize]) -> usize {
+        let Some(&first) = windows.first() else {
+            return 0;
+        };
+        let mut fewest = 3;
+        for p in self.empties(first) {
+            let rest: Vec<usize> = windows.iter().copied().filter(|&w| !self.windows[w].contains(&p)).collect();
+            let Some(&second) = rest.first() else {
+                return 1;
+            };
+            if self.empties(second).any(|q| rest.iter().all(|&w| self.windows[w].contains(&q))) {
+                fewest = 2;
+            }
+        }
+        fewest
+    }
+
+    // The position from the side of `player`, who is about to place two stones
+    fn evaluate(&mut self, player: Player) -> i32 {
+        self.nodes += 1;
+        let opponent = player.other();
+        if !self.threats(player).is_empty() {
+            return WIN;
+        }
+        if self.stones_to_block(&self.threats(opponent)) > 2 {
+            return -WIN;
+        }
+        let mut total = 0;
+        for w in 0..self.windows.len() {
+            if let Some(n) = self.count(w, player) {
+                total += WINDOW_SCORES[n];
+            }
+            if let Some(n) = self.count(w, opponent) {
+                total -= WINDOW_SCORES[n];
+            }
+        }
+        total
+    }
+
+    // How the position stands for the side that just moved, once `player`
+    // has answered with the best of its turns; None if `interrupted` says to
+    // stop before every answer has been tried
+    fn reply_value(&mut self, player: Player, interrupted: &dyn Fn() -> bool) -> Option<i32> {
+        let standing = -self.evaluate(player);
+        if standing.abs() >= WIN - 100 {
+            return Some(standing);
+        }
+        let mut value = standing;
+        for turn in self.turns(player, 2, REPLY_CANDIDATES) {
+            if interrupted() {
+                return None;
+            }
+            for &p in &turn {
+                self.board[p] = player;
+            }
+            let after = self.evaluate(player.other());
+            for &p in &turn {
+                self.board[p] = Player::None;
+            }
+            value = value.min(after);
+            if value <= -(WIN - 100) {
+                break;
+            }
+        }
+        Some(value)
+    }
+
+    // What a stone of `player` at the empty point `i` adds to its own windows
+    // and takes away from the opponent's
+    fn point_score(&self, i: usize, player: Player) -> i32 {
+        let mut total = 0;
+        for &w in &self.through[i] {
+            if let Some(n) = self.count(w, player) {
+                total += WINDOW_SCORES[n + 1] - WINDOW_SCORES[n];
+            }
+            if let Some(n) = self.count(w, player.other()) {
+                total += WINDOW_SCORES[n];
+            }
+        }
+        total
+    }
+
+    // Empty points near the stones, best first, always including the points
+    // that block the opponent's threats
+    fn candidates(&self, player: Player, limit: usize) -> Vec<usize> {
+        let mut scored: Vec<(i32, usize)> = (0..self.board.len())
+            .filter(|&i| self.board[i] == Player::None)
+            .filter(|&i| {
+                (-2..=2).any(|dy| {
+                    (-2..=2).any(|dx| offset(self.size, i, (dx, dy), 1).is_some_and(|p| self.board[p] != Player::None))
+                })
+            })
+            .map(|i| (self.point_score(i, player), i))
+            .collect();
+        scored.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+        let mut points: Vec<usize> = scored.into_iter().take(limit).map(|(_, i)| i).collect();
+        for w in self.threats(player.other()) {
+            for p in self.empties(w) {
+                if !points.contains(&p) {
+                    points.push(p);
+                }
+            }
+        }
+        points
+    }
+
+    // Turns of one or two stones built from the candidate points, the second
+    // stone chosen with the first already down so that the two can work
+    // together. A window that wins outright is the only turn worth looking at.
+    fn turns(&mut self, player: Player, stones: usize, limit: usize) -> Vec<Vec<usize>> {
+        if self.board.iter().all(|&c| c == Player::None) {
+            let center = self.size / 2;
+            return vec![vec![center * self.size + center]];
+        }
+        let candidates = self.candidates(player, limit);
+        if let Some(w) = (0..self.windows.len()).find(|&w| self.count(w, player).is_some_and(|n| n + stones >= 6)) {
+            let mut turn: Vec<usize> = self.empties(w).collect();
+            // Makes up the count when one stone is enough
+            if let Some(&extra) = candidates.iter().find(|p| !turn.contains(p)) {
+                if turn.len() < stones {
+                    turn.push(extra);
+                }
+            }
+            return vec![turn];
+        }
+        if stones == 1 {
+            return candidates.into_iter().map(|p| vec![p]).collect();
+        }
+        let mut turns: Vec<Vec<usize>> = Vec::new();
+        for a in candidates {
+            self.board[a] = player;
+            let seconds = self.candidates(player, (limit / 2).max(4));
+            self.board[a] = Player::None;
+            for b in seconds {
+                let turn = vec![a.min(b), a.max(b)];
+                if !turns.contains(&turn) {
+                    turns.push(turn);
+                }
+            }
+        }
+        turns
+    }
+}
+
+fn offset(size: usize, i: usize, (dx, dy): (isize, isize), k: isize) -> Option<usize> {
+    let x = (i % size) as isize + dx * k;
+    let y = (i / size) as isize + dy * k;
+    let size = size as isize;
+    (x >= 0 && y >= 0 && x < size && y < size).then(|| (y * size + x) as usize)
+}
@@ -1,12 +1,54 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
+use std::fmt;
+
+use crate::ai::Difficulty;
+use crate::opening::{Opening, OpeningChoice, OpeningRule, OpeningStep, Seat};
+use crate::renju::{self, Forbidden};
+use crate::rules::{GomokuRule, KoRule, RuleSet, Rules};
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum GameType {
-    Go,
-    Gomoku,
+    Go(Rules),
+    Gomoku(GomokuRule),
+    // Gomoku with double threes, double fours and overlines forbidden to Black
+    Renju,
+    // Six in a row, two stones a turn after Black's first
+    Connect6,
+    // Gomoku where X O O X takes the pair, won by five in a row or five pairs
+    Pente,
+    // Go won by the first capture, for teaching beginners
+    AtariGo,
+}
+
+impl GameType {
+    pub fn is_go(&self) -> bool {
+        matches!(self, GameType::Go(_))
+    }
+
+    // Games where a group without liberties is taken off the board
+    pub fn captures_groups(&self) -> bool {
+        matches!(self, GameType::Go(_) | GameType::AtariGo)
+    }
+
+    // Games won by five in a row
+    pub fn is_gomoku(&self) -> bool {
+        matches!(self, GameType::Gomoku(_) | GameType::Renju | GameType::Pente)
+    }
+
+    // What counts as a winning line for `player`; in Renju Black needs exactly five
+    pub fn gomoku_rule(&self, player: Player) -> GomokuRule {
+        match self {
+            GameType::Gomoku(rule) => *rule,
+            GameType::Renju if player == Player::Black => GomokuRule::Standard,
+            _ => GomokuRule::Freestyle,
+        }
+    }
 }
 
+// Pairs a Pente player has to capture to win
+pub const PENTE_PAIRS_TO_WIN: usize = 5;
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum Player {
     None,
@@ -14,6 +56,209 @@ pub enum Player {
     White,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Move {
+    Place(usize, usize),
+    Pass,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum GamePhase {
+    Playing,
+    // Black is placing free handicap stones
+    PlacingHandicap,
+    // Entered after two consecutive passes in Go
+    Scoring,
+    // Gomoku opening protocol, until colours are settled
+    Opening,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Handicap {
+    // Stones go on the standard star points
+    Fixed(usize),
+    // Black places the stones anywhere before White's first move
+    Free(usize),
+}
+
+impl Handicap {
+    pub fn stones(&self) -> usize {
+        match self {
+            Handicap::Fixed(n) | Handicap::Free(n) => *n,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct GameSettings {
+    pub size: usize,
+    pub game_type: GameType,
+    // Falls back to the rule set's default komi (0.5 with handicap) when not given
+    pub komi: Option<f32>,
+    #[serde(default)]
+    pub handicap: Option<Handicap>,
+    #[serde(default)]
+    pub difficulty: Difficulty,
+    // Swap, Swap2 and so on, for Gomoku and Renju
+    #[serde(default)]
+    pub opening: Option<OpeningRule>,
+}
+
+// Star points for a fixed handicap of 2-9 stones on 9x9, 13x13 and 19x19
+pub fn fixed_handicap_points(size: usize, stones: usize) -> Option<Vec<(usize, usize)>> {
+    let edge = match size {
+        9 => 2,
+        13 | 19 => 3,
+        _ => return None,
+    };
+    if !(2..=9).contains(&stones) {
+        return None;
+    }
+
+    let (lo, mid, hi) = (edge, size / 2, size - 1 - edge);
+    // Upper right, lower left, lower right, upper left
+    let corners = [(hi, lo), (lo, hi), (hi, hi), (lo, lo)];
+    // Left and right first, then top and bottom
+    let sides = [(lo, mid), (hi, mid), (mid, lo), (mid, hi)];
+
+    let mut points: Vec<(usize, usize)> = corners.iter().take(stones.min(4)).copied().collect();
+    if stones >= 6 {
+        let side_count = if stones >= 8 { 4 } else { 2 };
+        points.extend(&sides[..side_count]);
+    }
+    if stones % 2 == 1 && stones >= 5 {
+        points.push((mid, mid));
+    }
+    Some(points)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum GameError {
+    GameOver,
+    InScoringPhase,
+    PlacingHandicap,
+    InvalidHandicap,
+    NotInScoringPhase,
+    OutOfBounds,
+    Occupied,
+    NoStone,
+    PassNotAllowed,
+    NothingToUndo,
+    NothingToRedo,
+    Suicide,
+    Ko,
+    PositionalSuperko,
+    SituationalSuperko,
+    Forbidden(Forbidden),
+    InvalidOpening,
+    InOpening,
+    OutsideOpeningArea,
+    OpeningChoiceExpected,
+    UnavailableChoice,
+    NotOffered,
+    WrongStoneCount,
+}
+
+impl fmt::Display for GameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            GameError::GameOver => "Game is over",
+            GameError::InScoringPhase => "Game is in scoring phase",
+            GameError::PlacingHandicap => "Black is still placing handicap stones",
+            GameError::InvalidHandicap => "Handicap must be 2-9 stones, fixed handicap only on 9x9, 13x13 or 19x19",
+            GameError::NotInScoringPhase => "Not in scoring phase",
+            GameError::OutOfBounds => "Out of bounds",
+            GameError::Occupied => "Spot occupied",
+            GameError::NoStone => "No stone to mark",
+            GameError::PassNotAllowed => "Passing is only allowed in Go",
+            GameError::NothingToUndo => "No move to undo",
+            GameError::NothingToRedo => "No move to redo",
+            GameError::Suicide => "Suicide move",
+            GameError::Ko => "Ko rule violation",
+            GameError::PositionalSuperko => "Positional superko: this board position has occurred before",
+            GameError::SituationalSuperko => "Situational superko: this position has occurred before with the same player to move",
+            GameError::Forbidden(Forbidden::DoubleThree) => "Forbidden move: Black may not make two open threes at once",
+            GameError::Forbidden(Forbidden::DoubleFour) => "Forbidden move: Black may not make two fours at once",
+            GameError::Forbidden(Forbidden::Overline) => "Forbidden move: Black may not make six or more in a row",
+            GameError::InvalidOpening => "Opening rules are only for Gomoku and Renju",
+            GameError::InOpening => "Not possible before the opening is over",
+            GameError::OutsideOpeningArea => "This opening move has to be closer to the centre",
+            GameError::OpeningChoiceExpected => "The opening is waiting for a choice, not a move",
+            GameError::UnavailableChoice => "That choice is not available at this point of the opening",
+            GameError::NotOffered => "Pick one of the offered fifth moves",
+            GameError::WrongStoneCount => "A Connect6 turn is two stones, only Black's first is one",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl std::error::Error for GameError {}
+
+// Lets Tauri commands use `?` on game operations
+impl From<GameError> for String {
+    fn from(e: GameError) -> Self {
+        e.to_string()
+    }
+}
+
+// Fixed pseudo-random key for a stone of the given color on (x, y), derived
+// with splitmix64 so the table never has to be stored or shared.
+pub(crate) fn zobrist_key(x: usize, y: usize, player: Player) -> u64 {
+    let color = match player {
+        Player::Black => 1,
+        Player::White => 2,
+        Player::None => return 0,
+    };
+    let mut z = ((((y as u64) << 16) | x as u64) << 2 | color).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+// Stones captured by each player
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Prisoners {
+    pub black: usize,
+    pub white: usize,
+}
+
+// One entry of the move list, with everything needed to take it back
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MoveRecord {
+    pub player: Player,
+    pub mv: Move,
+    pub captured: Vec<(usize, usize)>,
+    // Own stones removed by a suicide, where the rules allow it
+    pub suicided: Vec<(usize, usize)>,
+    // Ko state before the move
+    pub previous_hash: u64,
+    pub previous_passes: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MoveResult {
+    // Opponent stones removed by this move
+    pub captured: Vec<(usize, usize)>,
+}
+
+impl Prisoners {
+    pub fn add(&mut self, player: Player, count: usize) {
+        match player {
+            Player::Black => self.black += count,
+            Player::White => self.white += count,
+            Player::None => {}
+        }
+    }
+
+    pub fn remove(&mut self, player: Player, count: usize) {
+        match player {
+            Player::Black => self.black -= count,
+            Player::White => self.white -= count,
+            Player::None => {}
+        }
+    }
+}
+
 impl Player {
     pub fn other(&self) -> Player {
         match self {
@@ -29,51 +274,188 @@ pub struct Game {
     pub board: Vec<Vec<Player>>,
     pub size: usize,
     pub current_turn: Player,
-    pub last_move: Option<(usize, usize)>,
-    // Simple Ko check: store hash of previous board states? 
-    // For simplicity, just store the previous board state to check for simple Ko.
-    pub previous_board: Option<Vec<Vec<Player>>>,
+    pub last_move: Option<Move>,
+    pub moves: Vec<MoveRecord>,
+    // Moves taken back by undo, most recent last
+    pub redo_stack: Vec<MoveRecord>,
+    pub consecutive_passes: usize,
+    pub phase: GamePhase,
+    // Stones marked dead during the scoring phase
+    pub dead_stones: HashSet<(usize, usize)>,
+    // Zobrist hash of the current board, and of every position so far
+    // together with the player to move, for ko and superko detection
+    pub position_hash: u64,
+    pub position_history: Vec<(u64, Player)>,
     pub game_type: GameType,
+    pub rules: RuleSet,
+    pub komi: f32,
+    pub handicap: usize,
+    // Free handicap stones Black still has to place
+    pub handicap_remaining: usize,
+    // Stones on the board before the first move (handicap or setup)
+    pub setup_stones: Vec<((usize, usize), Player)>,
+    pub prisoners: Prisoners,
+    pub last_captured: Vec<(usize, usize)>,
     pub winner: Option<Player>,
     pub is_draw: bool,
+    // Strength of the computer opponent
+    pub difficulty: Difficulty,
+    // Opening protocol, kept after it is over for who plays which colour
+    pub opening: Option<Opening>,
 }
 
 impl Game {
     pub fn new(size: usize, game_type: GameType) -> Self {
+        Game::with_settings(&GameSettings {
+            size,
+            game_type,
+            komi: None,
+            handicap: None,
+            difficulty: Difficulty::default(),
+            opening: None,
+        })
+        .expect("a game without handicap is always valid")
+    }
+
+    pub fn with_settings(settings: &GameSettings) -> Result<Self, GameError> {
+        let size = settings.size;
         let board = vec![vec![Player::None; size]; size];
-        Game {
+        // Only Go has a choice of rule set; Atari Go plays by the default, which
+        // forbids suicide, and the other game types just carry it
+        let rules = match settings.game_type {
+            GameType::Go(rules) => rules.rule_set(),
+            _ => Rules::Chinese.rule_set(),
+        };
+        let default_komi = if settings.handicap.is_some() { 0.5 } else { rules.default_komi };
+        let komi = match settings.game_type {
+            GameType::Go(_) => settings.komi.unwrap_or(default_komi),
+            _ => 0.0,
+        };
+        let mut game = Game {
             board,
             size,
             current_turn: Player::Black,
             last_move: None,
-            previous_board: None,
-            game_type,
+            moves: Vec::new(),
+            redo_stack: Vec::new(),
+            consecutive_passes: 0,
+            phase: GamePhase::Playing,
+            dead_stones: HashSet::new(),
+            position_hash: 0,
+            position_history: vec![(0, Player::Black)],
+            game_type: settings.game_type,
+            rules,
+            komi,
+            handicap: 0,
+            handicap_remaining: 0,
+            setup_stones: Vec::new(),
+            prisoners: Prisoners::default(),
+            last_captured: Vec::new(),
             winner: None,
             is_draw: false,
+            difficulty: settings.difficulty,
+            opening: None,
+        };
+
+        if let Some(handicap) = settings.handicap {
+            game.setup_handicap(handicap)?;
+        }
+        if let Some(rule) = settings.opening {
+            if !game.game_type.is_gomoku() {
+                return Err(GameError::InvalidOpening);
+            }
+            game.opening = Some(Opening::new(rule));
+            game.phase = GamePhase::Opening;
+        }
+        Ok(game)
+    }
+
+    fn setup_handicap(&mut self, handicap: Handicap) -> Result<(), GameError> {
+        let stones = handicap.stones();
+        if !self.game_type.is_go() || !(2..=9).contains(&stones) {
+            return Err(GameError::InvalidHandicap);
+        }
+        self.handicap = stones;
+
+        match handicap {
+            Handicap::Fixed(n) => {
+                let points = fixed_handicap_points(self.size, n).ok_or(GameError::InvalidHandicap)?;
+                for (x, y) in points {
+                    self.place_setup_stone(x, y, Player::Black);
+                }
+                self.start_after_handicap();
+            }
+            Handicap::Free(n) => {
+                self.phase = GamePhase::PlacingHandicap;
+                self.handicap_remaining = n;
+            }
+        }
+        Ok(())
+    }
+
+    fn place_setup_stone(&mut self, x: usize, y: usize, player: Player) {
+        self.board[y][x] = player;
+        self.position_hash ^= zobrist_key(x, y, player);
+        self.setup_stones.push(((x, y), player));
+    }
+
+    // Edits the position directly, as SGF setup properties do. Player::None clears the point.
+    pub fn setup_stone(&mut self, x: usize, y: usize, player: Player) -> Result<(), GameError> {
+        if x >= self.size || y >= self.size {
+            return Err(GameError::OutOfBounds);
+        }
+        let old = self.board[y][x];
+        if old != Player::None {
+            self.board[y][x] = Player::None;
+            self.position_hash ^= zobrist_key(x, y, old);
+            self.setup_stones.retain(|&(p, _)| p != (x, y));
+        }
+        if player != Player::None {
+            self.place_setup_stone(x, y, player);
         }
+        // Repetition is only judged from the edited position onwards
+        self.position_history = vec![(self.position_hash, self.current_turn)];
+        Ok(())
     }
 
-    pub fn play(&mut self, x: usize, y: usize) -> Result<bool, String> {
+    // White moves first once the handicap stones are down
+    fn start_after_handicap(&mut self) {
+        self.phase = GamePhase::Playing;
+        self.current_turn = Player::White;
+        self.position_history = vec![(self.position_hash, Player::White)];
+    }
+
+    pub fn play(&mut self, x: usize, y: usize) -> Result<MoveResult, GameError> {
         if self.winner.is_some() || self.is_draw {
-            return Err("Game is over".to_string());
+            return Err(GameError::GameOver);
+        }
+        if self.phase == GamePhase::Scoring {
+            return Err(GameError::InScoringPhase);
         }
         if x >= self.size || y >= self.size {
-            return Err("Out of bounds".to_string());
+            return Err(GameError::OutOfBounds);
         }
         if self.board[y][x] != Player::None {
-            return Err("Spot occupied".to_string());
+            return Err(GameError::Occupied);
         }
 
-        if self.game_type == GameType::Gomoku {
-            self.board[y][x] = self.current_turn;
-            self.last_move = Some((x, y));
-            
-            if self.check_gomoku_win(x, y) {
-                self.winner = Some(self.current_turn);
-            } else {
-                self.current_turn = self.current_turn.other();
+        if self.phase == GamePhase::PlacingHandicap {
+            self.place_setup_stone(x, y, Player::Black);
+            self.last_move = Some(Move::Place(x, y));
+            self.handicap_remaining -= 1;
+            if self.handicap_remaining == 0 {
+                self.start_after_handicap();
             }
-            return Ok(false);
+            return Ok(MoveResult::default());
+        }
+
+        if self.phase == GamePhase::Opening {
+            self.play_opening(x, y)?;
+            return Ok(MoveResult::default());
+        }
+        if !self.game_type.captures_groups() {
+            self.place_gomoku_stone(x, y)?;
+            return Ok(MoveResult::default());
         }
 
         let mut new_board = self.board.clone();
@@ -81,7 +463,6 @@ impl Game {
 
         // Check captures
         let opponent = self.current_turn.other();
-        let mut captured = false;
         let mut stones_to_remove = HashSet::new();
 
         let neighbors = [(0, 1), (0, -1), (1, 0), (-1, 0)];
@@ -92,53 +473,400 @@ impl Game {
             if nx >= 0 && nx < self.size as i32 && ny >= 0 && ny < self.size as i32 {
                 let nx = nx as usize;
                 let ny = ny as usize;
-                if new_board[ny][nx] == opponent {
-                    if !self.has_liberties(&new_board, nx, ny) {
-                        // Capture group
-                        let group = self.get_group(&new_board, nx, ny);
-                        for (gx, gy) in group {
-                            stones_to_remove.insert((gx, gy));
-                        }
-                        captured = true;
+                if new_board[ny][nx] == opponent && !self.has_liberties(&new_board, nx, ny) {
+                    // Capture group
+                    let group = self.get_group(&new_board, nx, ny);
+                    for (gx, gy) in group {
+                        stones_to_remove.insert((gx, gy));
                     }
                 }
             }
         }
 
-        for (rx, ry) in &stones_to_remove {
-            new_board[*ry][*rx] = Player::None;
+        let mut captured: Vec<(usize, usize)> = stones_to_remove.into_iter().collect();
+        captured.sort_unstable();
+        for &(rx, ry) in &captured {
+            new_board[ry][rx] = Player::None;
         }
 
         // Check suicide
-        if !captured {
-            if !self.has_liberties(&new_board, x, y) {
-                return Err("Suicide move".to_string());
+        let mut suicided = Vec::new();
+        if captured.is_empty() && !self.has_liberties(&new_board, x, y) {
+            if !self.rules.suicide_allowed {
+                return Err(GameError::Suicide);
+            }
+            // The whole group, including the new stone, goes to the opponent
+            suicided = self.get_group(&new_board, x, y);
+            for &(sx, sy) in &suicided {
+                new_board[sy][sx] = Player::None;
             }
         }
 
         // Check Ko
-        if let Some(prev) = &self.previous_board {
-            if new_board == *prev {
-                return Err("Ko rule violation".to_string());
-            }
+        let mut new_hash = self.position_hash ^ zobrist_key(x, y, self.current_turn);
+        for &(cx, cy) in &captured {
+            new_hash ^= zobrist_key(cx, cy, opponent);
+        }
+        for &(sx, sy) in &suicided {
+            new_hash ^= zobrist_key(sx, sy, self.current_turn);
         }
+        self.check_repetition(new_hash, opponent)?;
 
-        self.previous_board = Some(self.board.clone());
+        self.record_move(Move::Place(x, y), captured.clone(), suicided.clone());
         self.board = new_board;
-        self.last_move = Some((x, y));
-        self.current_turn = opponent;
+        self.position_hash = new_hash;
+        self.position_history.push((new_hash, opponent));
+        self.prisoners.add(self.current_turn, captured.len());
+        self.prisoners.add(opponent, suicided.len());
+        self.last_captured = captured.clone();
+        self.last_move = Some(Move::Place(x, y));
+        self.redo_stack.clear();
+        self.consecutive_passes = 0;
+
+        if self.game_type == GameType::AtariGo && !captured.is_empty() {
+            self.winner = Some(self.current_turn);
+        } else {
+            self.current_turn = opponent;
+        }
+        Ok(MoveResult { captured })
+    }
+
+    fn place_gomoku_stone(&mut self, x: usize, y: usize) -> Result<(), GameError> {
+        if let Some(kind) = self.forbidden_move(x, y) {
+            return Err(GameError::Forbidden(kind));
+        }
+        let player = self.current_turn;
+        let turn_over = self.stones_to_place() == 1;
+        let next = if turn_over { player.other() } else { player };
+        self.board[y][x] = player;
+        let captured = self.pair_captures(x, y);
+        for &(cx, cy) in &captured {
+            self.board[cy][cx] = Player::None;
+        }
+        self.record_move(Move::Place(x, y), captured.clone(), Vec::new());
+        self.position_hash ^= zobrist_key(x, y, player);
+        for &(cx, cy) in &captured {
+            self.position_hash ^= zobrist_key(cx, cy, player.other());
+        }
+        self.position_history.push((self.position_hash, next));
+        self.prisoners.add(player, captured.len());
+        self.last_captured = captured;
+        self.last_move = Some(Move::Place(x, y));
+        self.redo_stack.clear();
+
+        if self.check_gomoku_win(x, y) || self.captured_pairs(player) >= PENTE_PAIRS_TO_WIN {
+            self.winner = Some(player);
+        } else {
+            self.current_turn = next;
+        }
+        Ok(())
+    }
+
+    // Pente: opponent pairs that the stone just placed at (x, y) closes in
+    fn pair_captures(&self, x: usize, y: usize) -> Vec<(usize, usize)> {
+        if self.game_type != GameType::Pente {
+            return Vec::new();
+        }
+        let player = self.board[y][x];
+        let at = |k: i32, (dx, dy): (i32, i32)| {
+            let (nx, ny) = (x as i32 + dx * k, y as i32 + dy * k);
+            let inside = nx >= 0 && ny >= 0 && nx < self.size as i32 && ny < self.size as i32;
+            inside.then_some((nx as usize, ny as usize))
+        };
+        let mut captured = Vec::new();
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                if (dx, dy) == (0, 0) {
+                    continue;
+                }
+                let line: Option<Vec<(usize, usize)>> = (1..=3).map(|k| at(k, (dx, dy))).collect();
+                let Some(line) = line else { continue };
+                let colors: Vec<Player> = line.iter().map(|&(lx, ly)| self.board[ly][lx]).collect();
+                if colors == [player.other(), player.other(), player] {
+                    captured.extend_from_slice(&line[..2]);
+                }
+            }
+        }
+        captured
+    }
+
+    // Pairs `player` has taken in Pente
+    pub fn captured_pairs(&self, player: Player) -> usize {
+        match player {
+            Player::Black => self.prisoners.black / 2,
+            Player::White => self.prisoners.white / 2,
+            Player::None => 0,
+        }
+    }
+
+    // Stones the player to move still has to place before the turn passes;
+    // every turn in Connect6 is two stones apart from Black's first
+    pub fn stones_to_place(&self) -> usize {
+        if self.game_type != GameType::Connect6 {
+            return 1;
+        }
+        match self.moves.last() {
+            Some(last) if last.player != self.current_turn => 2,
+            _ => 1,
+        }
+    }
+
+    // Plays all stones of a turn, or none of them if one is illegal. A stone
+    // that wins ends the turn, any after it are not played.
+    pub fn play_turn(&mut self, stones: &[(usize, usize)]) -> Result<(), GameError> {
+        if stones.len() != self.stones_to_place() {
+            return Err(GameError::WrongStoneCount);
+        }
+        let before = self.clone();
+        for &(x, y) in stones {
+            if self.winner.is_some() {
+                break;
+            }
+            if let Err(e) = self.play(x, y) {
+                *self = before;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    // Why the player to move may not play at the empty point (x, y), in Renju
+    fn forbidden_move(&self, x: usize, y: usize) -> Option<Forbidden> {
+        if self.game_type != GameType::Renju || self.current_turn != Player::Black {
+            return None;
+        }
+        renju::forbidden_move(&self.board.concat(), self.size, y * self.size + x)
+    }
+
+    // A click during the opening: a stone, an offered fifth move, or the pick among them
+    fn play_opening(&mut self, x: usize, y: usize) -> Result<(), GameError> {
+        let mut opening = self.opening.clone().ok_or(GameError::InOpening)?;
+        match opening.step {
+            Some(OpeningStep::Place) => {
+                let center = self.size / 2;
+                if let Some(max) = opening.max_distance(self.moves.len()) {
+                    if x.abs_diff(center).max(y.abs_diff(center)) > max {
+                        return Err(GameError::OutsideOpeningArea);
+                    }
+                }
+                self.place_gomoku_stone(x, y)?;
+            }
+            Some(OpeningStep::Offer) => {
+                if opening.offers.contains(&(x, y)) {
+                    return Err(GameError::Occupied);
+                }
+                if let Some(kind) = self.forbidden_move(x, y) {
+                    return Err(GameError::Forbidden(kind));
+                }
+                opening.offer((x, y));
+                self.opening = Some(opening);
+                return Ok(());
+            }
+            Some(OpeningStep::Pick) => {
+                if !opening.offers.contains(&(x, y)) {
+                    return Err(GameError::NotOffered);
+                }
+                self.place_gomoku_stone(x, y)?;
+            }
+            Some(OpeningStep::Choose | OpeningStep::DeclareOffers) => return Err(GameError::OpeningChoiceExpected),
+            None => return Err(GameError::InOpening),
+        }
+        if self.winner.is_none() {
+            opening.stone_placed(self.moves.len());
+        }
+        self.end_opening_if_over(opening);
+        Ok(())
+    }
+
+    // Picks a colour, or another option the opening offers at this point
+    pub fn choose_opening(&mut self, choice: OpeningChoice) -> Result<(), GameError> {
+        if self.phase != GamePhase::Opening {
+            return Err(GameError::UnavailableChoice);
+        }
+        let mut opening = self.opening.clone().ok_or(GameError::UnavailableChoice)?;
+        if !opening.choices(self.moves.len()).contains(&choice) {
+            return Err(GameError::UnavailableChoice);
+        }
+        opening.choose(choice, self.moves.len());
+        self.end_opening_if_over(opening);
+        Ok(())
+    }
+
+    fn end_opening_if_over(&mut self, opening: Opening) {
+        if opening.is_over() {
+            self.phase = GamePhase::Playing;
+        }
+        self.opening = Some(opening);
+    }
+
+    // Which player has to act next, Black's player unless an opening swapped colours
+    pub fn seat_to_act(&self) -> Seat {
+        match &self.opening {
+            Some(opening) if !opening.is_over() => opening.actor,
+            Some(opening) => opening.seat_of(self.current_turn),
+            None if self.current_turn == Player::White => Seat::Second,
+            None => Seat::First,
+        }
+    }
+
+    pub fn pass(&mut self) -> Result<(), GameError> {
+        if self.winner.is_some() || self.is_draw {
+            return Err(GameError::GameOver);
+        }
+        if !self.game_type.is_go() {
+            return Err(GameError::PassNotAllowed);
+        }
+        match self.phase {
+            GamePhase::Playing => {}
+            GamePhase::PlacingHandicap => return Err(GameError::PlacingHandicap),
+            GamePhase::Scoring => return Err(GameError::InScoringPhase),
+            GamePhase::Opening => return Err(GameError::InOpening),
+        }
+
+        // A pass repeats the position, so a ko may be retaken afterwards
+        self.record_move(Move::Pass, Vec::new(), Vec::new());
+        self.last_move = Some(Move::Pass);
+        self.last_captured.clear();
+        self.redo_stack.clear();
+        self.consecutive_passes += 1;
+        self.current_turn = self.current_turn.other();
+        self.position_history.push((self.position_hash, self.current_turn));
+
+        if self.consecutive_passes >= 2 {
+            self.phase = GamePhase::Scoring;
+        }
+        Ok(())
+    }
 
-        Ok(captured)
+    fn record_move(&mut self, mv: Move, captured: Vec<(usize, usize)>, suicided: Vec<(usize, usize)>) {
+        self.moves.push(MoveRecord {
+            player: self.current_turn,
+            mv,
+            captured,
+            suicided,
+            previous_hash: self.position_hash,
+            previous_passes: self.consecutive_passes,
+        });
+    }
+
+    // Takes back the last move, including any stones it captured
+    pub fn undo(&mut self) -> Result<MoveRecord, GameError> {
+        if self.winner.is_some() || self.is_draw {
+            return Err(GameError::GameOver);
+        }
+        // Stones and choices of an opening protocol stand
+        match &self.opening {
+            _ if self.phase == GamePhase::Opening => return Err(GameError::InOpening),
+            Some(opening) if self.moves.len() <= opening.moves => return Err(GameError::NothingToUndo),
+            _ => {}
+        }
+        let record = self.moves.pop().ok_or(GameError::NothingToUndo)?;
+
+        if let Move::Place(x, y) = record.mv {
+            let opponent = record.player.other();
+            for &(sx, sy) in &record.suicided {
+                self.board[sy][sx] = record.player;
+            }
+            for &(cx, cy) in &record.captured {
+                self.board[cy][cx] = opponent;
+            }
+            self.board[y][x] = Player::None;
+            self.prisoners.remove(record.player, record.captured.len());
+            self.prisoners.remove(opponent, record.suicided.len());
+        }
+
+        self.position_history.pop();
+        self.position_hash = record.previous_hash;
+        self.consecutive_passes = record.previous_passes;
+        self.current_turn = record.player;
+        self.phase = GamePhase::Playing;
+        self.dead_stones.clear();
+        self.last_move = self.moves.last().map(|r| r.mv);
+        self.last_captured = self.moves.last().map(|r| r.captured.clone()).unwrap_or_default();
+
+        self.redo_stack.push(record.clone());
+        Ok(record)
+    }
+
+    // Takes back the last turn: both stones of a Connect6 turn, one move otherwise
+    pub fn undo_turn(&mut self) -> Result<(), GameError> {
+        let record = self.undo()?;
+        while self.moves.last().is_some_and(|last| last.player == record.player) {
+            if self.undo().is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Replays the most recently undone turn
+    pub fn redo_turn(&mut self) -> Result<(), GameError> {
+        let record = self.redo()?;
+        while self.current_turn == record.player && self.winner.is_none() && !self.redo_stack.is_empty() {
+            self.redo()?;
+        }
+        Ok(())
+    }
+
+    // Replays the most recently undone move
+    pub fn redo(&mut self) -> Result<MoveRecord, GameError> {
+        let record = self.redo_stack.pop().ok_or(GameError::NothingToRedo)?;
+
+        // Playing normally clears the redo stack, keep the rest of it
+        let remaining = std::mem::take(&mut self.redo_stack);
+        let result = match record.mv {
+            Move::Place(x, y) => self.play(x, y).map(|_| ()),
+            Move::Pass => self.pass(),
+        };
+        self.redo_stack = remaining;
+
+        match result {
+            Ok(()) => Ok(record),
+            Err(e) => {
+                self.redo_stack.push(record);
+                Err(e)
+            }
+        }
+    }
+
+    fn check_repetition(&self, new_hash: u64, next_turn: Player) -> Result<(), GameError> {
+        // Immediate recapture is reported as plain ko under every rule
+        let n = self.position_history.len();
+        if n >= 2 && self.position_history[n - 2].0 == new_hash {
+            return Err(GameError::Ko);
+        }
+
+        match self.rules.ko_rule {
+            KoRule::Simple => Ok(()),
+            KoRule::PositionalSuperko => {
+                if self.position_history.iter().any(|&(h, _)| h == new_hash) {
+                    Err(GameError::PositionalSuperko)
+                } else {
+                    Ok(())
+                }
+            }
+            KoRule::SituationalSuperko => {
+                if self.position_history.iter().any(|&(h, turn)| h == new_hash && turn == next_turn) {
+                    Err(GameError::SituationalSuperko)
+                } else {
+                    Ok(())
+                }
+            }
+        }
     }
 
     fn check_gomoku_win(&self, x: usize, y: usize) -> bool {
         let player = self.board[y][x];
         if player == Player::None { return false; }
+        let rule = self.game_type.gomoku_rule(player);
+        let six = self.game_type == GameType::Connect6;
         
         let directions = [(1, 0), (0, 1), (1, 1), (1, -1)];
         
         for (dx, dy) in directions.iter() {
             let mut count = 1;
+            // Whether an opponent's stone closes each end, for Caro
+            let mut blocked = (false, false);
             
             // Check forward
             let mut i = 1;
@@ -149,6 +877,7 @@ impl Game {
                 if self.board[ny as usize][nx as usize] == player {
                     count += 1;
                 } else {
+                    blocked.0 = self.board[ny as usize][nx as usize] == player.other();
                     break;
                 }
                 i += 1;
@@ -163,19 +892,20 @@ impl Game {
                 if self.board[ny as usize][nx as usize] == player {
                     count += 1;
                 } else {
+                    blocked.1 = self.board[ny as usize][nx as usize] == player.other();
                     break;
                 }
                 i += 1;
             }
             
-            if count >= 5 {
+            if (six && count >= 6) || (!six && rule.is_win(count, blocked)) {
                 return true;
             }
         }
         false
     }
 
-    fn has_liberties(&self, board: &Vec<Vec<Player>>, x: usize, y: usize) -> bool {
+    pub(crate) fn has_liberties(&self, board: &[Vec<Player>], x: usize, y: usize) -> bool {
         let color = board[y][x];
         if color == Player::None {
             return true;
@@ -209,7 +939,7 @@ impl Game {
         false
     }
 
-    fn get_group(&self, board: &Vec<Vec<Player>>, x: usize, y: usize) -> Vec<(usize, usize)> {
+    pub(crate) fn get_group(&self, board: &[Vec<Player>], x: usize, y: usize) -> Vec<(usize, usize)> {
         let color = board[y][x];
         let mut group = Vec::new();
         if color == Player::None {
@@ -242,9 +972,15 @@ impl Game {
         group
     }
 
+    // Quick Go heuristic, used where a full search is not worth it such as
+    // placing free handicap stones
     pub fn get_ai_move(&self) -> Option<(usize, usize)> {
-        if self.game_type == GameType::Gomoku {
-            return self.get_gomoku_ai_move();
+        // Free handicap: take the usual star points first
+        if self.phase == GamePhase::PlacingHandicap {
+            let star_points = fixed_handicap_points(self.size, 9).unwrap_or_default();
+            if let Some(&p) = star_points.iter().find(|&&(x, y)| self.board[y][x] == Player::None) {
+                return Some(p);
+            }
         }
 
         let mut best_score = -1000;
@@ -272,11 +1008,11 @@ impl Game {
                 }
 
                 let mut sim_game = self.clone();
-                if let Ok(captured) = sim_game.play(x, y) {
+                if let Ok(result) = sim_game.play(x, y) {
                     let mut score = 0;
                     
                     // 1. Capture is good
-                    if captured {
+                    if !result.captured.is_empty() {
                         score += 100;
                     }
 
@@ -319,117 +1055,6 @@ impl Game {
         Some(best_moves[idx])
     }
 
-    fn get_gomoku_ai_move(&self) -> Option<(usize, usize)> {
-        let mut best_score = -1;
-        let mut best_moves = Vec::new();
-        let size = self.size;
-        let opponent = self.current_turn.other();
-
-        // If board is empty, play center
-        let center = size / 2;
-        if self.board[center][center] == Player::None {
-            return Some((center, center));
-        }
-
-        for y in 0..size {
-            for x in 0..size {
-                if self.board[y][x] != Player::None {
-                    continue;
-                }
-
-                // Simple heuristic: Attack score + Defense score
-                let attack_score = self.evaluate_gomoku_pos(x, y, self.current_turn);
-                let defense_score = self.evaluate_gomoku_pos(x, y, opponent);
-                
-                // Weight defense slightly less than attack unless it's critical
-                let score = attack_score + defense_score;
-
-                if score > best_score {
-                    best_score = score;
-                    best_moves.clear();
-                    best_moves.push((x, y));
-                } else if score == best_score {
-                    best_moves.push((x, y));
-                }
-            }
-        }
-
-        if best_moves.is_empty() {
-            return None;
-        }
-        
-        use rand::Rng;
-        let mut rng = rand::thread_rng();
-        let idx = rng.gen_range(0..best_moves.len());
-        Some(best_moves[idx])
-    }
-
-    fn evaluate_gomoku_pos(&self, x: usize, y: usize, player: Player) -> i32 {
-        let directions = [(1, 0), (0, 1), (1, 1), (1, -1)];
-        let mut total_score = 0;
-
-        for (dx, dy) in directions.iter() {
-            let mut count = 1;
-            let mut open_ends = 0;
-            
-            // Check forward
-            let mut i = 1;
-            loop {
-                let nx = x as i32 + dx * i;
-                let ny = y as i32 + dy * i;
-                if nx < 0 || nx >= self.size as i32 || ny < 0 || ny >= self.size as i32 { break; }
-                let cell = self.board[ny as usize][nx as usize];
-                if cell == player {
-                    count += 1;
-                } else if cell == Player::None {
-                    open_ends += 1;
-                    break;
-                } else {
-                    break;
-                }
-                i += 1;
-            }
-            
-            // Check backward
-            let mut i = 1;
-            loop {
-                let nx = x as i32 - dx * i;
-                let ny = y as i32 - dy * i;
-                if nx < 0 || nx >= self.size as i32 || ny < 0 || ny >= self.size as i32 { break; }
-                let cell = self.board[ny as usize][nx as usize];
-                if cell == player {
-                    count += 1;
-                } else if cell == Player::None {
-                    open_ends += 1;
-                    break;
-                } else {
-                    break;
-                }
-                i += 1;
-            }
-
-            if count >= 5 {
-                total_score += 100000;
-            } else if count == 4 {
-                if open_ends > 0 {
-                    total_score += 10000; // Open 4 or Closed 4 (still dangerous)
-                    if open_ends == 2 { total_score += 10000; } // Open 4 is winning
-                }
-            } else if count == 3 {
-                if open_ends == 2 {
-                    total_score += 1000; // Open 3
-                } else if open_ends == 1 {
-                    total_score += 100;
-                }
-            } else if count == 2 {
-                if open_ends == 2 {
-                    total_score += 100;
-                }
-            }
-        }
-        total_score
-    }
-
     fn get_liberty_count(&self, x: usize, y: usize) -> usize {
         let group = self.get_group(&self.board, x, y);
         let mut liberties = HashSet::new();
@@ -451,3 +1076,176 @@ impl Game {
         liberties.len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn go(rules: Rules) -> Game {
+        Game::new(9, GameType::Go(rules))
+    }
+
+    // Black to move can take the white stone on (4, 4) from (5, 4)
+    fn ko(rules: Rules) -> Game {
+        let mut game = go(rules);
+        for (x, y) in [(3, 4), (4, 3), (4, 5)] {
+            game.setup_stone(x, y, Player::Black).unwrap();
+        }
+        for (x, y) in [(4, 4), (5, 3), (6, 4), (5, 5)] {
+            game.setup_stone(x, y, Player::White).unwrap();
+        }
+        game
+    }
+
+    fn board_hash(game: &Game) -> u64 {
+        let mut hash = 0;
+        for (y, row) in game.board.iter().enumerate() {
+            for (x, &cell) in row.iter().enumerate() {
+                hash ^= zobrist_key(x, y, cell);
+            }
+        }
+        hash
+    }
+
+    #[test]
+    fn ko_cannot_be_retaken_at_once() {
+        for rules in [Rules::Chinese, Rules::Japanese, Rules::Aga, Rules::NewZealand, Rules::TrompTaylor] {
+            let mut game = ko(rules);
+            assert_eq!(game.play(5, 4).unwrap().captured, [(4, 4)]);
+            assert_eq!(game.play(4, 4), Err(GameError::Ko));
+            game.play(8, 8).unwrap();
+            game.play(0, 8).unwrap();
+            assert_eq!(game.play(4, 4).unwrap().captured, [(5, 4)]);
+            assert_eq!(game.play(5, 4), Err(GameError::Ko));
+        }
+    }
+
+    #[test]
+    fn superko_looks_at_the_whole_history() {
+        let history = vec![(1, Player::Black), (2, Player::White), (3, Player::Black)];
+        let cases = [
+            (Rules::Japanese, Ok(()), Ok(())),
+            (Rules::Chinese, Err(GameError::PositionalSuperko), Err(GameError::PositionalSuperko)),
+            (Rules::NewZealand, Err(GameError::SituationalSuperko), Ok(())),
+        ];
+        for (rules, same_player, other_player) in cases {
+            let mut game = go(rules);
+            game.position_history = history.clone();
+            assert_eq!(game.check_repetition(1, Player::Black), same_player);
+            assert_eq!(game.check_repetition(1, Player::White), other_player);
+            // The position before the last move is plain ko under every rule
+            assert_eq!(game.check_repetition(2, Player::Black), Err(GameError::Ko));
+            assert_eq!(game.check_repetition(4, Player::Black), Ok(()));
+        }
+    }
+
+    #[test]
+    fn suicide_that_repeats_the_board() {
+        // A lone stone in White's corner takes itself off, leaving the board as it was
+        for (rules, result) in [(Rules::TrompTaylor, Err(GameError::PositionalSuperko)), (Rules::NewZealand, Ok(()))] {
+            let mut game = go(rules);
+            game.setup_stone(1, 0, Player::White).unwrap();
+            game.setup_stone(0, 1, Player::White).unwrap();
+            let hash = game.position_hash;
+            assert_eq!(game.play(0, 0).map(|_| ()), result);
+            assert_eq!(game.position_hash, hash);
+            assert_eq!(game.board[0][0], Player::None);
+            if result.is_ok() {
+                assert_eq!((game.prisoners.white, game.current_turn), (1, Player::White));
+            }
+        }
+    }
+
+    #[test]
+    fn undo_and_redo_keep_the_position_history() {
+        let mut game = ko(Rules::Chinese);
+        let snapshot = |game: &Game| (game.position_history.clone(), game.position_hash, game.board.clone(), game.prisoners);
+        let mut snapshots = vec![snapshot(&game)];
+        game.play(5, 4).unwrap();
+        snapshots.push(snapshot(&game));
+        game.play(8, 8).unwrap();
+        snapshots.push(snapshot(&game));
+        game.pass().unwrap();
+        snapshots.push(snapshot(&game));
+        game.play(4, 4).unwrap();
+        snapshots.push(snapshot(&game));
+        assert_eq!(game.position_history.len(), 5);
+
+        for expected in snapshots.iter().rev().skip(1) {
+            game.undo().unwrap();
+            assert_eq!(&snapshot(&game), expected);
+            assert_eq!(game.position_hash, board_hash(&game));
+        }
+        assert_eq!(game.undo().map(|_| ()), Err(GameError::NothingToUndo));
+        for expected in &snapshots[1..] {
+            game.redo().unwrap();
+            assert_eq!(&snapshot(&game), expected);
+        }
+
+        // Back to just after Black's capture, the ko is still seen
+        for _ in 0..3 {
+            game.undo().unwrap();
+        }
+        assert_eq!(game.play(4, 4), Err(GameError::Ko));
+        assert_eq!(game.redo_stack.len(), 3);
+        game.play(0, 0).unwrap();
+        assert!(game.redo_stack.is_empty());
+        assert_eq!(game.position_history.len(), 3);
+    }
+
+    fn pente(black: &[(usize, usize)], white: &[(usize, usize)]) -> Game {
+        let mut game = Game::new(15, GameType::Pente);
+        for &(x, y) in black {
+            game.setup_stone(x, y, Player::Black).unwrap();
+        }
+        for &(x, y) in white {
+            game.setup_stone(x, y, Player::White).unwrap();
+        }
+        game
+    }
+
+    #[test]
+    fn pente_takes_a_closed_in_pair() {
+        let mut game = pente(&[(3, 7), (3, 9), (3, 11), (6, 11)], &[(4, 7), (5, 7), (4, 9), (5, 9), (6, 9), (4, 11)]);
+        game.play(6, 7).unwrap();
+        assert_eq!(game.board[7][4], Player::None);
+        assert_eq!(game.board[7][5], Player::None);
+        assert_eq!(game.last_captured, [(5, 7), (4, 7)]);
+        assert_eq!(game.prisoners.black, 2);
+        assert_eq!(game.captured_pairs(Player::Black), 1);
+        assert_eq!(game.position_hash, board_hash(&game));
+
+        // Moving into a pair's gap is safe, and three in a row are not a pair
+        game.play(5, 11).unwrap();
+        assert_eq!(game.board[11][5], Player::White);
+        game.play(7, 9).unwrap();
+        assert_eq!(game.board[9][4], Player::White);
+        assert_eq!(game.prisoners, Prisoners { black: 2, white: 0 });
+
+        for _ in 0..3 {
+            game.undo().unwrap();
+        }
+        assert_eq!(game.board[7][4], Player::White);
+        assert_eq!(game.board[7][5], Player::White);
+        assert_eq!(game.captured_pairs(Player::Black), 0);
+    }
+
+    #[test]
+    fn pente_five_pairs_win() {
+        let rows = [0, 2, 4, 6, 8];
+        let black: Vec<_> = rows.iter().map(|&y| (0, y)).collect();
+        let white: Vec<_> = rows.iter().flat_map(|&y| [(1, y), (2, y)]).collect();
+        let mut game = pente(&black, &white);
+        for (i, &y) in rows.iter().enumerate() {
+            assert_eq!(game.winner, None);
+            game.play(3, y).unwrap();
+            assert_eq!(game.captured_pairs(Player::Black), i + 1);
+            if i + 1 < PENTE_PAIRS_TO_WIN {
+                game.play(14, y + 1).unwrap();
+            }
+        }
+        assert_eq!(game.winner, Some(Player::Black));
+        assert_eq!(game.prisoners, Prisoners { black: 10, white: 0 });
+        assert_eq!(game.play(14, 14), Err(GameError::GameOver));
+    }
+}
@@ -0,0 +1,895 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::ai::{self, Progress, PROGRESS_INTERVAL};
+use crate::game::{zobrist_key, Game, GameType, Move, Player, PENTE_PAIRS_TO_WIN};
+use crate::renju;
+use crate::rules::GomokuRule;
+
+const WIN: i32 = 1_000_000;
+const INFINITY: i32 = 2 * WIN;
+// Value of a five-point window holding n stones of one colour and none of the other
+const WINDOW_SCORES: [i32; 6] = [0, 1, 12, 150, 2_000, 100_000];
+const DIRECTIONS: [(isize, isize); 4] = [(1, 0), (0, 1), (1, 1), (1, -1)];
+// Moves searched per node after ordering, more at the root
+const BRANCHING: usize = 12;
+const ROOT_BRANCHING: usize = 24;
+// Attacker moves in a threat sequence, and the node budget for the whole threat search
+const VCF_DEPTH: u32 = 12;
+const VCT_DEPTH: u32 = 5;
+const THREAT_NODES: u64 = 300_000;
+// The threat search may use this fraction of the time limit; the rest is the main search's
+const THREAT_TIME_DIVISOR: u32 = 4;
+// Mixed into the hash when White is to move
+const WHITE_TO_MOVE: u64 = 0x9e37_79b9_7f4a_7c15;
+// Multiplied by the pairs Black and White have captured in Pente and mixed into the hash
+const BLACK_PAIR: u64 = 0xbf58_476d_1ce4_e5b9;
+const WHITE_PAIR: u64 = 0x94d0_49bb_1331_11eb;
+// Pente: worth of having taken n pairs, of a pair open to capture, and of a capturing move
+const PAIR_SCORES: [i32; PENTE_PAIRS_TO_WIN] = [0, 300, 800, 2_000, 6_000];
+const CAPTURE_THREAT: i32 = 80;
+const CAPTURE_MOVE: i32 = 1_500;
+
+// How deep and how long the Gomoku engine may search
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct GomokuConfig {
+    pub max_depth: u32,
+    pub time_limit_ms: Option<u64>,
+    // Look for forced wins by fours and threes before the main search
+    pub threat_search: bool,
+    // Random amount, in evaluation points, added to each move's score at the root
+    pub noise: i32,
+}
+
+impl Default for GomokuConfig {
+    fn default() -> Self {
+        GomokuConfig {
+            max_depth: 10,
+            time_limit_ms: Some(3000),
+            threat_search: true,
+            noise: 0,
+        }
+    }
+}
+
+// Picks a move for the player to move: a forced win by continuous fours (VCF)
+// or threats (VCT) if there is one, otherwise iterative-deepening alpha-beta
+pub fn best_move(
+    game: &Game,
+    config: &GomokuConfig,
+    stop: &AtomicBool,
+    report: &mut dyn FnMut(Progress),
+) -> Option<(usize, usize)> {
+    let mut search = Search::new(game, config, stop, report);
+    let i = search.choose(config)?;
+    Some((i % game.size, i / game.size))
+}
+
+// The most promising moves by a quick static look, best first
+pub fn ranked_moves(game: &Game, limit: usize) -> Vec<(usize, usize)> {
+    let stop = AtomicBool::new(false);
+    let mut ignore = |_| {};
+    let mut search = Search::new(game, &GomokuConfig::default(), &stop, &mut ignore);
+    let player = search.to_move;
+    if search.board.iter().all(|&c| c == Player::None) {
+        let center = game.size / 2;
+        return vec![(center, center)];
+    }
+    search
+        .ordered_moves(player, None, limit)
+        .into_iter()
+        .map(|i| (i % game.size, i / game.size))
+        .collect()
+}
+
+// Black's chance of winning by a search `depth` moves deep, for judging
+// opening positions; depth 0 is the static evaluation
+pub fn black_win_rate(game: &Game, depth: u32) -> f32 {
+    let stop = AtomicBool::new(false);
+    let mut ignore = |_| {};
+    let config = GomokuConfig {
+        max_depth: depth,
+        time_limit_ms: Some(500),
+        threat_search: false,
+        noise: 0,
+    };
+    let mut search = Search::new(game, &config, &stop, &mut ignore);
+    let player = search.to_move;
+    let rate = ai::win_rate(search.negamax(depth, -INFINITY, INFINITY, 0), WIN, WINDOW_SCORES[4]);
+    if player == Player::Black {
+        rate
+    } else {
+        1.0 - rate
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Clone, Copy)]
+struct TtEntry {
+    depth: u32,
+    score: i32,
+    bound: Bound,
+    best: Option<usize>,
+}
+
+struct Search<'a> {
+    size: usize,
+    board: Vec<Player>,
+    to_move: Player,
+    // Decides what wins, and in Renju which points Black may not play
+    game_type: GameType,
+    hash: u64,
+    // Static evaluation from Black's side, updated as stones come and go
+    score: i32,
+    // Pente: pairs taken by Black and by White
+    pairs: (usize, usize),
+    tt: HashMap<u64, TtEntry>,
+    deadline: Option<Instant>,
+    node_budget: u64,
+    nodes: u64,
+    aborted: bool,
+    stop: &'a AtomicBool,
+    report: &'a mut dyn FnMut(Progress),
+    last_report: Instant,
+    // Result of the deepest finished iteration, for progress reports
+    best: Option<(usize, i32)>,
+}
+
+impl<'a> Search<'a> {
+    fn new(
+        game: &Game,
+        config: &GomokuConfig,
+        stop: &'a AtomicBool,
+        report: &'a mut dyn FnMut(Progress),
+    ) -> Self {
+        let size = game.size;
+        let mut search = Search {
+            size,
+            board: vec![Player::None; size * size],
+            to_move: game.current_turn,
+            game_type: game.game_type,
+            hash: 0,
+            score: 0,
+            pairs: (game.captured_pairs(Player::Black), game.captured_pairs(Player::White)),
+            tt: HashMap::new(),
+            deadline: config
+                .time_limit_ms
+                .map(|ms| Instant::now() + Duration::from_millis(ms)),
+            node_budget: u64::MAX,
+            nodes: 0,
+            aborted: false,
+            stop,
+            report,
+            last_report: Instant::now(),
+            best: None,
+        };
+        for (y, row) in game.board.iter().enumerate() {
+            for (x, &cell) in row.iter().enumerate() {
+                if cell != Player::None {
+                    search.place(y * size + x, cell);
+                }
+            }
+        }
+        search
+    }
+
+    fn choose(&mut self, config: &GomokuConfig) -> Option<usize> {
+        let me = self.to_move;
+        let opponent = me.other();
+
+        if self.board.iter().all(|&c| c == Player::None) {
+            let center = self.size / 2;
+            return Some(center * self.size + center);
+        }
+        if let Some(&win) = self.winning_points(me).first() {
+            return Some(win);
+        }
+        // A four on the board has to be blocked, whatever else is going on;
+        // in Pente taking a pair out of it may do as well, which the search sees
+        if self.game_type != GameType::Pente {
+            if let Some(block) = self.winning_points(opponent).into_iter().find(|&p| !self.forbidden(p, me)) {
+                return Some(block);
+            }
+        }
+
+        // Threat sequences assume stones stay put, which captures break
+        if config.threat_search && self.game_type != GameType::Pente {
+            let deadline = self.deadline;
+            self.deadline = config
+                .time_limit_ms
+                .map(|ms| Instant::now() + Duration::from_millis(ms) / THREAT_TIME_DIVISOR);
+            self.node_budget = THREAT_NODES;
+            let threat_win = self
+                .attack(me, VCF_DEPTH, true)
+                .or_else(|| self.attack(me, VCT_DEPTH, false));
+            if threat_win.is_some() {
+                return threat_win;
+            }
+            self.deadline = deadline;
+        }
+
+        if self.stop.load(Ordering::Relaxed) {
+            return self.ordered_moves(me, None, 1).first().copied();
+        }
+        self.node_budget = u64::MAX;
+        self.aborted = false;
+        for depth in 1..=config.max_depth.max(1) {
+            let (score, mv) = self.search_root(depth, config.noise);
+            if self.aborted {
+                break;
+            }
+            if let Some(m) = mv {
+                self.best = Some((m, score));
+                self.send_progress();
+            }
+            if score.abs() >= WIN - 100 {
+                break;
+            }
+        }
+        // Out of time before the first iteration finished: the best move by ordering
+        self.best
+            .map(|(m, _)| m)
+            .or_else(|| self.ordered_moves(me, None, 1).first().copied())
+    }
+
+    fn send_progress(&mut self) {
+        self.last_report = Instant::now();
+        let size = self.size;
+        let progress = Progress {
+            best_move: self.best.map(|(m, _)| Move::Place(m % size, m / size)),
+            nodes: self.nodes,
+            win_rate: self.best.map_or(0.5, |(_, score)| ai::win_rate(score, WIN, WINDOW_SCORES[4])),
+        };
+        (self.report)(progress);
+    }
+
+    fn offset(&self, i: usize, (dx, dy): (isize, isize), k: isize) -> Option<usize> {
+        let x = (i % self.size) as isize + dx * k;
+        let y = (i / self.size) as isize + dy * k;
+        let size = self.size as isize;
+        (x >= 0 && y >= 0 && x < size && y < size).then(|| (y * size + x) as usize)
+    }
+
+    // Whether filling the five points from `start` would win for `player`: not
+    // with one of its own stones just past an end under the exact-five rule, nor
+    // with both ends closed under Caro
+    fn window_can_win(&self, i: usize, d: (isize, isize), start: isize, player: Player) -> bool {
+        let before = self.offset(i, d, start - 1).map(|p| self.board[p]);
+        let after = self.offset(i, d, start + 5).map(|p| self.board[p]);
+        match self.game_type.gomoku_rule(player) {
+            GomokuRule::Freestyle => true,
+            GomokuRule::Standard => before != Some(player) && after != Some(player),
+            GomokuRule::Caro => !(before == Some(player.other()) && after == Some(player.other())),
+        }
+    }
+
+    // Sum of the windows through `i` and those ending next to it, whose worth
+    // can depend on `i` too, from Black's side
+    fn local_score(&self, i: usize) -> i32 {
+        let mut total = 0;
+        for d in DIRECTIONS {
+            for start in -5..=1 {
+                let mut counts = (0, 0);
+                let mut inside = true;
+                for k in start..start + 5 {
+                    match self.offset(i, d, k).map(|p| self.board[p]) {
+                        Some(Player::Black) => counts.0 += 1,
+                        Some(Player::White) => counts.1 += 1,
+                        Some(Player::None) => {}
+                        None => {
+                            inside = false;
+                            break;
+                        }
+                    }
+                }
+                if inside {
+                    total += match counts {
+                        (b, 0) if self.window_can_win(i, d, start, Player::Black) => WINDOW_SCORES[b],
+                        (0, w) if self.window_can_win(i, d, start, Player::White) => -WINDOW_SCORES[w],
+                        _ => 0,
+                    };
+                }
+            }
+        }
+        total
+    }
+
+    fn place(&mut self, i: usize, player: Player) {
+        self.score -= self.local_score(i);
+        self.board[i] = player;
+        self.score += self.local_score(i);
+        self.hash ^= zobrist_key(i % self.size, i / self.size, player);
+    }
+
+    fn remove(&mut self, i: usize) {
+        let player = self.board[i];
+        self.score -= self.local_score(i);
+        self.board[i] = Player::None;
+        self.score += self.local_score(i);
+        self.hash ^= zobrist_key(i % self.size, i / self.size, player);
+    }
+
+    // Whether a stone of `player` at the empty point `i` would complete a winning line
+    fn makes_five(&self, i: usize, player: Player) -> bool {
+        let rule = self.game_type.gomoku_rule(player);
+        DIRECTIONS.iter().any(|&d| {
+            // Stones in line on one side, and whether the opponent closes that end
+            let side = |sign: isize| {
+                let run = (1..6)
+                    .take_while(|&k| self.offset(i, d, sign * k).is_some_and(|p| self.board[p] == player))
+                    .count();
+                let end = self.offset(i, d, sign * (run as isize + 1));
+                (run, end.is_some_and(|p| self.board[p] == player.other()))
+            };
+            let (ahead, behind) = (side(1), side(-1));
+            rule.is_win(1 + ahead.0 + behind.0, (behind.1, ahead.1))
+        })
+    }
+
+    fn pairs_of(&self, player: Player) -> usize {
+        if player == Player::Black {
+            self.pairs.0
+        } else {
+            self.pairs.1
+        }
+    }
+
+    fn add_pairs(&mut self, player: Player, count: isize) {
+        let pairs = if player == Player::Black { &mut self.pairs.0 } else { &mut self.pairs.1 };
+        *pairs = pairs.wrapping_add_signed(count);
+    }
+
+    // Pente: the two stones of each opponent pair that a stone of `player`
+    // at the empty point `i` would close in
+    fn captures_at(&self, i: usize, player: Player) -> Vec<usize> {
+        let mut captured = Vec::new();
+        if self.game_type != GameType::Pente {
+            return captured;
+        }
+        for d in DIRECTIONS {
+            for sign in [-1, 1] {
+                let line: Option<Vec<usize>> = (1..=3).map(|k| self.offset(i, d, sign * k)).collect();
+                if let Some(line) = line {
+                    let colors = [self.board[line[0]], self.board[line[1]], self.board[line[2]]];
+                    if colors == [player.other(), player.other(), player] {
+                        captured.extend_from_slice(&line[..2]);
+                    }
+                }
+            }
+        }
+        captured
+    }
+
+    // Places a stone and takes the pairs it captures, which are returned for `take_back`
+    fn play_stone(&mut self, i: usize, player: Player) -> Vec<usize> {
+        let captured = self.captures_at(i, player);
+        self.place(i, player);
+        for &p in &captured {
+            self.remove(p);
+        }
+        self.add_pairs(player, captured.len() as isize / 2);
+        captured
+    }
+
+    fn take_back(&mut self, i: usize, player: Player, captured: &[usize]) {
+        self.add_pairs(player, -(captured.len() as isize / 2));
+        for &p in captured {
+            self.place(p, player.other());
+        }
+        self.remove(i);
+    }
+
+    // Whether a stone of `player` at the empty point `i` wins, by a line or in Pente by a last pair
+    fn wins_at(&self, i: usize, player: Player) -> bool {
+        self.makes_five(i, player)
+            || (self.game_type == GameType::Pente
+                && self.pairs_of(player) + self.captures_at(i, player).len() / 2 >= PENTE_PAIRS_TO_WIN)
+    }
+
+    // Pente: taken pairs and pairs open to capture, from Black's side
+    fn capture_score(&self) -> i32 {
+        if self.game_type != GameType::Pente {
+            return 0;
+        }
+        let mut total = PAIR_SCORES[self.pairs.0.min(PENTE_PAIRS_TO_WIN - 1)] - PAIR_SCORES[self.pairs.1.min(PENTE_PAIRS_TO_WIN - 1)];
+        for i in 0..self.board.len() {
+            if self.board[i] == Player::None {
+                continue;
+            }
+            let player = self.board[i];
+            for d in DIRECTIONS {
+                for sign in [-1, 1] {
+                    let threat = (1..=3)
+                        .map(|k| self.offset(i, d, sign * k).map(|p| self.board[p]))
+                        .eq([Some(player.other()), Some(player.other()), Some(Player::None)]);
+                    if threat {
+                        let pairs = self.pairs_of(player) as i32;
+                        let value = CAPTURE_THREAT * (1 + pairs);
+                        total += if player == Player::Black { value } else { -value };
+                    }
+                }
+            }
+        }
+        total
+    }
+
+    fn forbidden(&self, i: usize, player: Player) -> bool {
+        self.game_type == GameType::Renju && player == Player::Black && renju::forbidden_move(&self.board, self.size, i).is_some()
+    }
+
+    // Empty points next to some stone that `player` may play, the only places worth considering
+    fn candidates(&self, player: Player) -> Vec<usize> {
+        (0..self.board.len())
+            .filter(|&i| self.board[i] == Player::None && !self.forbidden(i, player))
+            .filter(|&i| {
+                (-2..=2).any(|dy| {
+                    (-2..=2).any(|dx| self.offset(i, (dx, dy), 1).is_some_and(|p| self.board[p] != Player::None))
+                })
+            })
+            .collect()
+    }
+
+    fn winning_points(&self, player: Player) -> Vec<usize> {
+        (0..self.board.len())
+            .filter(|&i| self.board[i] == Player::None && self.wins_at(i, player))
+            .collect()
+    }
+
+    // Points that would complete five on the lines through `i`
+    fn completions(&self, i: usize, player: Player) -> Vec<usize> {
+        let mut points = Vec::new();
+        for d in DIRECTIONS {
+            for k in -4..=4 {
+                if let Some(p) = self.offset(i, d, k) {
+                    if self.board[p] == Player::None && self.makes_five(p, player) && !points.contains(&p) {
+                        points.push(p);
+                    }
+                }
+            }
+        }
+        points
+    }
+
+    // Whether some window through `i` holds at least `count` stones of `player` and none of the opponent's
+    fn has_window(&self, i: usize, player: Player, count: usize) -> bool {
+        DIRECTIONS.iter().any(|&d| {
+            (-4..=0).any(|start| {
+                let mut own = 0;
+                for k in start..start + 5 {
+                    match self.offset(i, d, k).map(|p| self.board[p]) {
+                        Some(c) if c == player => own += 1,
+                        Some(Player::None) => {}
+                        _ => return false,
+                    }
+                }
+                own >= count
+            })
+        })
+    }
+
+    // Candidates that could take part in a threat of `player` with `count` stones already in line
+    fn threat_candidates(&self, player: Player, count: usize) -> Vec<usize> {
+        self.candidates(player)
+            .into_iter()
+            .filter(|&i| self.has_window(i, player, count))
+            .collect()
+    }
+
+    fn makes_open_four(&mut self, i: usize, player: Player) -> bool {
+        self.place(i, player);
+        let open = self.completions(i, player).len() >= 2;
+        self.remove(i);
+        open
+    }
+
+    // Moves after which `player` threatens five at two points, which cannot both be stopped
+    fn open_four_moves(&mut self, player: Player) -> Vec<usize> {
+        self.threat_candidates(player, 3)
+            .into_iter()
+            .filter(|&i| self.makes_open_four(i, player))
+            .collect()
+    }
+
+    fn out_of_budget(&mut self) -> bool {
+        self.nodes += 1;
+        if self.nodes > self.node_budget {
+            self.aborted = true;
+        } else if self.nodes & 1023 == 0 {
+            let now = Instant::now();
+            if self.deadline.is_some_and(|d| now >= d) || self.stop.load(Ordering::Relaxed) {
+                self.aborted = true;
+            } else if now - self.last_report >= PROGRESS_INTERVAL {
+                self.send_progress();
+            }
+        }
+        self.aborted
+    }
+
+    // Threat-space search: a move for `attacker` that wins by continuous fours
+    // (only_fours) or by fours and threes, whatever the defender replies
+    fn attack(&mut self, attacker: Player, depth: u32, only_fours: bool) -> Option<usize> {
+        let defender = attacker.other();
+        if let Some(&win) = self.winning_points(attacker).first() {
+            return Some(win);
+        }
+        if depth == 0 || self.out_of_budget() || !self.winning_points(defender).is_empty() {
+            return None;
+        }
+
+        // Fours first: they leave the defender a single reply
+        let mut fours = Vec::new();
+        let mut threes = Vec::new();
+        for i in self.threat_candidates(attacker, 2) {
+            self.place(i, attacker);
+            if !self.completions(i, attacker).is_empty() {
+                fours.push(i);
+            } else if !only_fours && !self.open_four_moves(attacker).is_empty() {
+                threes.push(i);
+            }
+            self.remove(i);
+        }
+
+        for m in fours.into_iter().chain(threes) {
+            self.place(m, attacker);
+            let holds = self.defend(attacker, depth - 1, only_fours);
+            self.remove(m);
+            if !holds {
+                return Some(m);
+            }
+            if self.aborted {
+                return None;
+            }
+        }
+        None
+    }
+
+    // Whether the defender, to move, survives the attacker's threats
+    fn defend(&mut self, attacker: Player, depth: u32, only_fours: bool) -> bool {
+        let defender = attacker.other();
+        if !self.winning_points(defender).is_empty() || self.out_of_budget() {
+            return true;
+        }
+
+        let fives = self.winning_points(attacker);
+        let replies: Vec<usize> = match fives.len() {
+            0 => {
+                let open_fours = self.open_four_moves(attacker);
+                if open_fours.is_empty() {
+                    // No threat left, the attack has run out
+                    return true;
+                }
+                // Points on the threatened lines that stop every open four, or counter fours
+                let mut points: Vec<usize> = self.threat_candidates(defender, 3);
+                for &m in &open_fours {
+                    for d in DIRECTIONS {
+                        for k in -4..=4 {
+                            if let Some(p) = self.offset(m, d, k) {
+                                if self.board[p] == Player::None && !points.contains(&p) {
+                                    points.push(p);
+                                }
+                            }
+                        }
+                    }
+                }
+                let mut replies = Vec::new();
+                for d in points {
+                    if self.forbidden(d, defender) {
+                        continue;
+                    }
+                    self.place(d, defender);
+                    let counter = !self.completions(d, defender).is_empty();
+                    let stops = open_fours.iter().all(|&m| m == d || !self.makes_open_four(m, attacker));
+                    if counter || stops {
+                        replies.push(d);
+                    }
+                    self.remove(d);
+                }
+                replies
+            }
+            // Black loses to a four that can only be blocked at a forbidden point
+            1 => fives.into_iter().filter(|&p| !self.forbidden(p, defender)).collect(),
+            _ => return false,
+        };
+
+        for d in replies {
+            self.place(d, defender);
+            let counter = self.completions(d, defender);
+            let holds = match counter.len() {
+                0 => self.attack(attacker, depth, only_fours).is_none(),
+                // The attacker has to block the counter four and the defender moves again
+                1 => {
+                    if self.makes_five(counter[0], attacker) {
+                        false
+                    } else if self.forbidden(counter[0], attacker) {
+                        true
+                    } else {
+                        self.place(counter[0], attacker);
+                        let holds = self.defend(attacker, depth, only_fours);
+                        self.remove(counter[0]);
+                        holds
+                    }
+                }
+                _ => true,
+            };
+            self.remove(d);
+            if holds || self.aborted {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn key(&self) -> u64 {
+        let hash = self.hash
+            ^ (self.pairs.0 as u64).wrapping_mul(BLACK_PAIR)
+            ^ (self.pairs.1 as u64).wrapping_mul(WHITE_PAIR);
+        if self.to_move == Player::White {
+            hash ^ WHITE_TO_MOVE
+        } else {
+            hash
+        }
+    }
+
+    // Attack and defence value of a move, for ordering
+    fn move_score(&self, i: usize, player: Player) -> i32 {
+        let mut total = 0;
+        for d in DIRECTIONS {
+            for start in -4..=0 {
+                let mut own = 0;
+                let mut theirs = 0;
+                let mut inside = true;
+                for k in start..start + 5 {
+                    match self.offset(i, d, k).map(|p| self.board[p]) {
+                        Some(c) if c == player => own += 1,
+                        Some(Player::None) => {}
+                        Some(_) => theirs += 1,
+                        None => {
+                            inside = false;
+                            break;
+                        }
+                    }
+                }
+                if inside {
+                    if theirs == 0 && self.window_can_win(i, d, start, player) {
+                        total += WINDOW_SCORES[own + 1];
+                    }
+                    if own == 0 && self.window_can_win(i, d, start, player.other()) {
+                        total += WINDOW_SCORES[theirs + 1] * 4 / 5;
+                    }
+                }
+            }
+        }
+        // Taking a pair, or the point where the opponent would take one
+        total += CAPTURE_MOVE * (self.captures_at(i, player).len() / 2) as i32;
+        total += CAPTURE_MOVE * 4 / 5 * (self.captures_at(i, player.other()).len() / 2) as i32;
+        total
+    }
+
+    fn ordered_moves(&mut self, player: Player, first: Option<usize>, limit: usize) -> Vec<usize> {
+        // Making five ends the game, whatever the opponent threatens
+        let wins = self.winning_points(player);
+        if !wins.is_empty() {
+            return wins;
+        }
+        // With a four against us, blocking it is the only move; in Pente so
+        // is taking a pair, which may break the four up
+        let mut forced: Vec<usize> = self
+            .winning_points(player.other())
+            .into_iter()
+            .filter(|&i| !self.forbidden(i, player))
+            .collect();
+        if !forced.is_empty() && self.game_type == GameType::Pente {
+            for i in self.candidates(player) {
+                if !forced.contains(&i) && !self.captures_at(i, player).is_empty() {
+                    forced.push(i);
+                }
+            }
+        }
+        if !forced.is_empty() {
+            return forced;
+        }
+        let mut moves: Vec<(i32, usize)> = self
+            .candidates(player)
+            .into_iter()
+            .map(|i| (if Some(i) == first { i32::MAX } else { self.move_score(i, player) }, i))
+            .collect();
+        moves.sort_unstable_by_key(|&(score, _)| std::cmp::Reverse(score));
+        moves.into_iter().take(limit).map(|(_, i)| i).collect()
+    }
+
+    fn evaluate(&self, player: Player) -> i32 {
+        let score = self.score + self.capture_score();
+        if player == Player::Black {
+            score
+        } else {
+            -score
+        }
+    }
+
+    fn search_root(&mut self, depth: u32, noise: i32) -> (i32, Option<usize>) {
+        let player = self.to_move;
+        let first = self.tt.get(&self.key()).and_then(|e| e.best);
+        let mut rng = rand::thread_rng();
+        let mut alpha = -INFINITY;
+        let mut best = None;
+        for m in self.ordered_moves(player, first, ROOT_BRANCHING) {
+            let mut score = self.score_move(m, depth, alpha, INFINITY, 0);
+            if self.aborted {
+                break;
+            }
+            // Noise never hides a win or a loss
+            if noise > 0 && score.abs() < WIN - 100 {
+                score += rng.gen_range(-noise..=noise);
+            }
+            if score > alpha || best.is_none() {
+                alpha = score;
+                best = Some(m);
+            }
+        }
+        if !self.aborted {
+            let key = self.key();
+            self.tt.insert(
+                key,
+                TtEntry {
+                    depth,
+                    score: alpha,
+                    bound: Bound::Exact,
+                    best,
+                },
+            );
+        }
+        (alpha, best)
+    }
+
+    fn score_move(&mut self, m: usize, depth: u32, alpha: i32, beta: i32, ply: i32) -> i32 {
+        let player = self.to_move;
+        if self.wins_at(m, player) {
+            return WIN - ply;
+        }
+        let captured = self.play_stone(m, player);
+        self.to_move = player.other();
+        let score = -self.negamax(depth - 1, -beta, -alpha, ply + 1);
+        self.to_move = player;
+        self.take_back(m, player, &captured);
+        score
+    }
+
+    fn negamax(&mut self, depth: u32, mut alpha: i32, beta: i32, ply: i32) -> i32 {
+        if self.out_of_budget() {
+            return 0;
+        }
+        let player = self.to_move;
+        let key = self.key();
+        let mut first = None;
+        if let Some(entry) = self.tt.get(&key) {
+            first = entry.best;
+            if entry.depth >= depth {
+                let score = from_tt(entry.score, ply);
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower if score >= beta => return score,
+                    Bound::Upper if score <= alpha => return score,
+                    _ => {}
+                }
+            }
+        }
+
+        if depth == 0 {
+            // A four for the side to move wins, two fours against it lose
+            if !self.winning_points(player).is_empty() {
+                return WIN - ply - 1;
+            }
+            if self.winning_points(player.other()).len() >= 2 {
+                return -(WIN - ply - 2);
+            }
+            return self.evaluate(player);
+        }
+
+        let moves = self.ordered_moves(player, first, BRANCHING);
+        if moves.is_empty() {
+            return 0;
+        }
+
+        let original_alpha = alpha;
+        let mut best = -INFINITY;
+        let mut best_move = None;
+        for m in moves {
+            let score = self.score_move(m, depth, alpha, beta, ply);
+            if self.aborted {
+                return 0;
+            }
+            if score > best {
+                best = score;
+                best_move = Some(m);
+            }
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        let bound = if best <= original_alpha {
+            Bound::Upper
+        } else if best >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.tt.insert(
+            key,
+            TtEntry {
+                depth,
+                score: to_tt(best, ply),
+                bound,
+                best: best_move,
+            },
+        );
+        best
+    }
+}
+
+// Win and loss scores count plies from the root; the table keeps them counted
+// from the stored position, so that they are right when it is reached at another ply
+fn to_tt(score: i32, ply: i32) -> i32 {
+    if score >= WIN - 100 {
+        score + ply
+    } else if score <= -(WIN - 100) {
+        score - ply
+    } else {
+        score
+    }
+}
+
+fn from_tt(score: i32, ply: i32) -> i32 {
+    if score >= WIN - 100 {
+        score - ply
+    } else if score <= -(WIN - 100) {
+        score + ply
+    } else {
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::GomokuRule;
+
+    #[test]
+    fn own_five_comes_before_blocking() {
+        // Black has a four closed at one end, White an open four
+        let mut game = Game::new(15, GameType::Gomoku(GomokuRule::Freestyle));
+        for x in 2..6 {
+            game.setup_stone(x, 2, Player::Black).unwrap();
+            game.setup_stone(x, 10, Player::White).unwrap();
+        }
+        game.setup_stone(1, 2, Player::White).unwrap();
+        game.current_turn = Player::Black;
+
+        let stop = AtomicBool::new(false);
+        let mut ignore = |_| {};
+        let config = GomokuConfig { max_depth: 2, time_limit_ms: None, threat_search: false, noise: 0 };
+        let mut search = Search::new(&game, &config, &stop, &mut ignore);
+        assert_eq!(search.ordered_moves(Player::Black, None, BRANCHING), [2 * 15 + 6]);
+        assert_eq!(search.negamax(2, -INFINITY, INFINITY, 0), WIN);
+        assert_eq!(black_win_rate(&game, 2), 1.0);
+    }
+
+    #[test]
+    fn table_scores_move_with_the_ply() {
+        // A win two plies below a position stored at ply 3 is two plies below it at ply 1 too
+        assert_eq!(from_tt(to_tt(WIN - 5, 3), 1), WIN - 3);
+        assert_eq!(from_tt(to_tt(-(WIN - 6), 4), 2), -(WIN - 4));
+        assert_eq!(from_tt(to_tt(1_234, 7), 2), 1_234);
+    }
+}
@@ -0,0 +1,646 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::AtomicBool;
+
+use crate::ai;
+use crate::game::{Game, GameError, GamePhase, GameSettings, GameType, Handicap, Move, Player};
+use crate::rules::Rules;
+
+// GTP column letters; I is skipped to avoid confusion with J
+const COLUMNS: &[u8] = b"ABCDEFGHJKLMNOPQRSTUVWXYZ";
+
+#[derive(Debug)]
+pub enum GtpError {
+    Io(io::Error),
+    // The engine answered with "? message"
+    Engine(String),
+    // The engine said something that is not a GTP response
+    Protocol(String),
+    Closed,
+}
+
+impl fmt::Display for GtpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GtpError::Io(e) => write!(f, "GTP engine I/O error: {}", e),
+            GtpError::Engine(message) => write!(f, "GTP engine error: {}", message),
+            GtpError::Protocol(message) => write!(f, "GTP protocol error: {}", message),
+            GtpError::Closed => write!(f, "GTP engine exited"),
+        }
+    }
+}
+
+impl From<GtpError> for String {
+    fn from(e: GtpError) -> Self {
+        e.to_string()
+    }
+}
+
+impl From<io::Error> for GtpError {
+    fn from(e: io::Error) -> Self {
+        GtpError::Io(e)
+    }
+}
+
+pub fn color(player: Player) -> &'static str {
+    match player {
+        Player::White => "W",
+        _ => "B",
+    }
+}
+
+// Board coordinates to a GTP vertex such as "D4". Row 1 is at the bottom.
+pub fn vertex(x: usize, y: usize, size: usize) -> String {
+    format!("{}{}", COLUMNS[x] as char, size - y)
+}
+
+pub fn format_move(mv: Move, size: usize) -> String {
+    match mv {
+        Move::Place(x, y) => vertex(x, y, size),
+        Move::Pass => "pass".to_string(),
+    }
+}
+
+pub fn parse_color(text: &str) -> Option<Player> {
+    match text.to_ascii_lowercase().as_str() {
+        "b" | "black" => Some(Player::Black),
+        "w" | "white" => Some(Player::White),
+        _ => None,
+    }
+}
+
+// A vertex or "pass", case-insensitively
+pub fn parse_move(text: &str, size: usize) -> Option<Move> {
+    let text = text.trim().to_ascii_uppercase();
+    if text == "PASS" {
+        return Some(Move::Pass);
+    }
+    let column = *text.as_bytes().first()?;
+    let x = COLUMNS.iter().position(|&c| c == column)?;
+    let row: usize = text.get(1..)?.parse().ok()?;
+    if x >= size || row == 0 || row > size {
+        return None;
+    }
+    Some(Move::Place(x, size - row))
+}
+
+// GTP extensions that tell an engine the rule set, best first: KataGo's own
+// command and the one from KGS, which has no Tromp-Taylor
+const RULES_COMMANDS: [&str; 2] = ["kata-set-rules", "kgs-rules"];
+
+fn rules_name(command: &str, rules: Rules) -> Option<&'static str> {
+    match (command, rules) {
+        (_, Rules::Chinese) => Some("chinese"),
+        (_, Rules::Japanese) => Some("japanese"),
+        (_, Rules::Aga) => Some("aga"),
+        ("kata-set-rules", Rules::NewZealand) => Some("new-zealand"),
+        (_, Rules::NewZealand) => Some("new_zealand"),
+        ("kata-set-rules", Rules::TrompTaylor) => Some("tromp-taylor"),
+        _ => None,
+    }
+}
+
+// What the engine's board was last set up with
+struct EngineBoard {
+    size: usize,
+    rules: Option<Rules>,
+    komi: f32,
+    setup: Vec<((usize, usize), Player)>,
+    moves: Vec<(Player, Move)>,
+}
+
+// An external Go engine, such as GNU Go or KataGo, driven over the Go Text Protocol
+pub struct GtpEngine {
+    // None when the engine is not a child process of ours
+    child: Option<Child>,
+    stdin: Box<dyn Write + Send>,
+    stdout: Box<dyn BufRead + Send>,
+    pub name: String,
+    // The command the engine takes the rule set with, if it knows one
+    rules_command: Option<&'static str>,
+    board: Option<EngineBoard>,
+}
+
+impl GtpEngine {
+    pub fn spawn(program: &str, args: &[String]) -> Result<Self, GtpError> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().ok_or(GtpError::Closed)?;
+        let stdout = BufReader::new(child.stdout.take().ok_or(GtpError::Closed)?);
+        GtpEngine::start(Some(child), Box::new(stdin), Box::new(stdout))
+    }
+
+    // Asks the engine for its name and the commands it knows
+    fn start(child: Option<Child>, stdin: Box<dyn Write + Send>, stdout: Box<dyn BufRead + Send>) -> Result<Self, GtpError> {
+        let mut engine = GtpEngine {
+            child,
+            stdin,
+            stdout,
+            name: String::new(),
+            rules_command: None,
+            board: None,
+        };
+
+        let name = engine.send("name")?;
+        engine.name = match engine.send("version") {
+            Ok(version) if !version.is_empty() => format!("{} {}", name, version),
+            _ => name,
+        };
+        // Optional in GTP; an engine without it is simply not told the rules
+        if let Ok(commands) = engine.send("list_commands") {
+            let known: Vec<&str> = commands.lines().map(str::trim).collect();
+            engine.rules_command = RULES_COMMANDS.into_iter().find(|c| known.contains(c));
+        }
+        Ok(engine)
+    }
+
+    // Sends one command and returns the text of a successful response
+    pub fn send(&mut self, command: &str) -> Result<String, GtpError> {
+        writeln!(self.stdin, "{}", command)?;
+        self.stdin.flush()?;
+
+        // A response is "=" or "?", the text, and then an empty line
+        let mut lines: Vec<String> = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.stdout.read_line(&mut line)? == 0 {
+                return Err(GtpError::Closed);
+            }
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.trim().is_empty() {
+                if lines.is_empty() {
+                    continue;
+                }
+                break;
+            }
+            lines.push(line.to_string());
+        }
+
+        let text = lines.join("\n");
+        // Skip the status character and the command id, if the engine echoes one
+        let body = || text[1..].trim_start_matches(|c: char| c.is_ascii_digit()).trim().to_string();
+        match text.as_bytes()[0] {
+            b'=' => Ok(body()),
+            b'?' => Err(GtpError::Engine(body())),
+            _ => Err(GtpError::Protocol(format!("unexpected response \"{}\"", text))),
+        }
+    }
+
+    // Brings the engine's board up to date with the game. Moves added since the
+    // last call are simply played; anything else sets the board up from scratch.
+    fn sync(&mut self, game: &Game) -> Result<(), GtpError> {
+        let rules = match game.game_type {
+            GameType::Go(rules) => Some(rules),
+            _ => None,
+        };
+        let told_rules = match (self.rules_command, rules) {
+            (Some(command), Some(rules)) => rules_name(command, rules).map(|name| (command, name)),
+            _ => None,
+        };
+        // An engine that knows the rules adds the handicap compensation itself;
+        // any other one gets it in the komi
+        let komi = match told_rules {
+            Some(_) => game.komi,
+            None => game.komi + game.rules.handicap_compensation.points(game.handicap),
+        };
+        let target = EngineBoard {
+            size: game.size,
+            rules,
+            komi,
+            setup: game.setup_stones.clone(),
+            moves: game.moves.iter().map(|record| (record.player, record.mv)).collect(),
+        };
+
+        let played = match &self.board {
+            Some(board)
+                if board.size == target.size
+                    && board.rules == target.rules
+                    && board.komi == target.komi
+                    && board.setup == target.setup
+                    && target.moves.starts_with(&board.moves) =>
+            {
+                board.moves.len()
+            }
+            _ => {
+                self.board = None;
+                self.send(&format!("boardsize {}", target.size))?;
+                self.send("clear_board")?;
+                if let Some((command, name)) = told_rules {
+                    self.send(&format!("{} {}", command, name))?;
+                }
+                self.send(&format!("komi {}", target.komi))?;
+                self.set_up(game)?;
+                0
+            }
+        };
+        for &(player, mv) in &target.moves[played..] {
+            self.send(&format!("play {} {}", color(player), format_move(mv, target.size)))?;
+        }
+        self.board = Some(target);
+        Ok(())
+    }
+
+    fn set_up(&mut self, game: &Game) -> Result<(), GtpError> {
+        let size = game.size;
+        let stones = &game.setup_stones;
+        // Handicap stones go in as handicap so the engine scores the game the same way
+        if game.handicap >= 2 && stones.iter().all(|&(_, player)| player == Player::Black) {
+            let vertices: Vec<String> = stones.iter().map(|&((x, y), _)| vertex(x, y, size)).collect();
+            self.send(&format!("set_free_handicap {}", vertices.join(" ")))?;
+        } else {
+            for &((x, y), player) in stones {
+                self.send(&format!("play {} {}", color(player), vertex(x, y, size)))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Asks the engine to move for the player to move. None means it resigned.
+    pub fn genmove(&mut self, game: &Game) -> Result<Option<Move>, GtpError> {
+        self.sync(game)?;
+        let player = game.current_turn;
+        let reply = self.send(&format!("genmove {}", color(player)))?;
+        if reply.eq_ignore_ascii_case("resign") {
+            return Ok(None);
+        }
+        let mv = parse_move(&reply, game.size)
+            .ok_or_else(|| GtpError::Protocol(format!("bad move \"{}\"", reply)))?;
+        // The engine has played the move on its own board
+        if let Some(board) = &mut self.board {
+            board.moves.push((player, mv));
+        }
+        Ok(Some(mv))
+    }
+}
+
+impl Drop for GtpEngine {
+    fn drop(&mut self) {
+        let _ = writeln!(self.stdin, "quit");
+        let _ = self.stdin.flush();
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+const COMMANDS: &[&str] = &[
+    "protocol_version",
+    "name",
+    "version",
+    "known_command",
+    "list_commands",
+    "quit",
+    "boardsize",
+    "clear_board",
+    "komi",
+    "fixed_handicap",
+    "place_free_handicap",
+    "set_free_handicap",
+    "play",
+    "genmove",
+    "reg_genmove",
+    "undo",
+    "final_score",
+    "showboard",
+    "time_settings",
+    "time_left",
+];
+
+// The built-in engine behind a GTP interface, for GTP controllers such as
+// Sabaki or gogui-twogtp
+pub struct GtpServer {
+    // Size, rules, komi and difficulty for each new board
+    settings: GameSettings,
+    game: Game,
+}
+
+impl GtpServer {
+    pub fn new(settings: GameSettings) -> Result<Self, GameError> {
+        let game = Game::with_settings(&settings)?;
+        Ok(GtpServer { settings, game })
+    }
+
+    // Answers commands until "quit" or the end of the input
+    pub fn run(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            // Drop comments and control characters, and turn tabs into spaces
+            let line: String = line
+                .split('#')
+                .next()
+                .unwrap_or("")
+                .chars()
+                .filter_map(|c| match c {
+                    '\t' => Some(' '),
+                    c if c.is_control() => None,
+                    c => Some(c),
+                })
+                .collect();
+            let mut words = line.split_whitespace().peekable();
+            let id = match words.peek() {
+                Some(word) if word.chars().all(|c| c.is_ascii_digit()) => words.next().unwrap_or(""),
+                _ => "",
+            };
+            let command = match words.next() {
+                Some(command) => command,
+                None => continue,
+            };
+            let args: Vec<&str> = words.collect();
+
+            match self.handle(command, &args) {
+                Ok(text) => write!(output, "={} {}\n\n", id, text)?,
+                Err(message) => write!(output, "?{} {}\n\n", id, message)?,
+            }
+            output.flush()?;
+            if command == "quit" {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, command: &str, args: &[&str]) -> Result<String, String> {
+        let size = self.game.size;
+        match command {
+            "protocol_version" => Ok("2".to_string()),
+            "name" => Ok("go-game".to_string()),
+            "version" => Ok(env!("CARGO_PKG_VERSION").to_string()),
+            "known_command" => Ok(args.first().is_some_and(|c| COMMANDS.contains(c)).to_string()),
+            "list_commands" => Ok(COMMANDS.join("\n")),
+            "quit" => Ok(String::new()),
+            "boardsize" => {
+                let size = arg::<usize>(args, 0)?;
+                if !(2..=COLUMNS.len()).contains(&size) {
+                    return Err("unacceptable size".to_string());
+                }
+                self.settings.size = size;
+                self.new_board(None).map_err(|e| e.to_string())?;
+                Ok(String::new())
+            }
+            "clear_board" => {
+                self.new_board(None).map_err(|e| e.to_string())?;
+                Ok(String::new())
+            }
+            "komi" => {
+                let komi = arg::<f32>(args, 0)?;
+                self.settings.komi = Some(komi);
+                self.game.komi = komi;
+                Ok(String::new())
+            }
+            // The engine's own choice of free handicap is the usual star points
+            "fixed_handicap" | "place_free_handicap" => {
+                let stones = arg::<usize>(args, 0)?;
+                self.check_empty()?;
+                self.new_board(Some(Handicap::Fixed(stones)))
+                    .map_err(|_| "invalid number of stones".to_string())?;
+                let vertices: Vec<String> = self
+                    .game
+                    .setup_stones
+                    .iter()
+                    .map(|&((x, y), _)| vertex(x, y, size))
+                    .collect();
+                Ok(vertices.join(" "))
+            }
+            "set_free_handicap" => {
+                self.check_empty()?;
+                let points = args
+                    .iter()
+                    .map(|v| match parse_move(v, size) {
+                        Some(Move::Place(x, y)) => Ok((x, y)),
+                        _ => Err("syntax error".to_string()),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.new_board(Some(Handicap::Free(points.len())))
+                    .map_err(|_| "invalid number of stones".to_string())?;
+                for (x, y) in points {
+                    if self.game.play(x, y).is_err() {
+                        let _ = self.new_board(None);
+                        return Err("bad vertex list".to_string());
+                    }
+                }
+                Ok(String::new())
+            }
+            "play" => {
+                let player = args.first().and_then(|c| parse_color(c)).ok_or("syntax error")?;
+                let mv = args.get(1).and_then(|v| parse_move(v, size)).ok_or("syntax error")?;
+                self.play(player, mv).map_err(|_| "illegal move".to_string())?;
+                Ok(String::new())
+            }
+            "genmove" | "reg_genmove" => {
+                let player = args.first().and_then(|c| parse_color(c)).ok_or("syntax error")?;
+                let mut game = self.game.clone();
+                if game.phase == GamePhase::Scoring {
+                    game.resume_play().map_err(|e| e.to_string())?;
+                }
+                game.current_turn = player;
+                let stop = AtomicBool::new(false);
+                let mv = ai::choose_move(&game, &game.difficulty.strength(), &stop, &mut |_| {}).unwrap_or(Move::Pass);
+                if command == "genmove" {
+                    self.play(player, mv).map_err(|e| e.to_string())?;
+                }
+                Ok(format_move(mv, size))
+            }
+            "undo" => {
+                self.game.undo().map_err(|_| "cannot undo".to_string())?;
+                Ok(String::new())
+            }
+            "final_score" => {
+                let result = self.game.score().result;
+                Ok(if result == "Draw" { "0".to_string() } else { result })
+            }
+            "showboard" => Ok(self.board_text()),
+            // Searches keep to the time limit of the chosen difficulty
+            "time_settings" | "time_left" => Ok(String::new()),
+            _ => Err("unknown command".to_string()),
+        }
+    }
+
+    fn new_board(&mut self, handicap: Option<Handicap>) -> Result<(), GameError> {
+        self.settings.handicap = handicap;
+        self.game = Game::with_settings(&self.settings)?;
+        Ok(())
+    }
+
+    fn check_empty(&self) -> Result<(), String> {
+        if self.game.moves.is_empty() && self.game.setup_stones.is_empty() {
+            Ok(())
+        } else {
+            Err("board not empty".to_string())
+        }
+    }
+
+    // Plays for the given colour, whoever's turn it is
+    fn play(&mut self, player: Player, mv: Move) -> Result<(), GameError> {
+        if self.game.phase == GamePhase::Scoring {
+            self.game.resume_play()?;
+        }
+        self.game.current_turn = player;
+        match mv {
+            Move::Place(x, y) => self.game.play(x, y).map(|_| ()),
+            Move::Pass => self.game.pass(),
+        }
+    }
+
+    fn board_text(&self) -> String {
+        let size = self.game.size;
+        let columns: String = COLUMNS[..size].iter().map(|&c| format!(" {}", c as char)).collect();
+        let mut text = format!("\n   {}\n", columns);
+        for (y, row) in self.game.board.iter().enumerate() {
+            let stones: String = row
+                .iter()
+                .map(|p| match p {
+                    Player::Black => " X",
+                    Player::White => " O",
+                    Player::None => " .",
+                })
+                .collect();
+            text.push_str(&format!("{:2} {}\n", size - y, stones));
+        }
+        text.push_str(&format!(
+            "Captured: black {}, white {}",
+            self.game.prisoners.black, self.game.prisoners.white
+        ));
+        text
+    }
+}
+
+fn arg<T: std::str::FromStr>(args: &[&str], index: usize) -> Result<T, String> {
+    args.get(index)
+        .and_then(|a| a.parse().ok())
+        .ok_or_else(|| "syntax error".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{script, transcript, Sent};
+
+    // An engine that gives the scripted responses in order, after answering
+    // the handshake with the given command list
+    fn fake_engine(commands: &str, responses: &[&str]) -> (GtpEngine, Sent) {
+        let mut text = format!("= Fake\n\n= 1.0\n\n= {}\n\n", commands);
+        for response in responses {
+            text.push_str(response);
+            text.push_str("\n\n");
+        }
+        let sent = Sent::default();
+        let engine = GtpEngine::start(None, Box::new(sent.clone()), script(&text)).unwrap();
+        (engine, sent)
+    }
+
+    fn go(size: usize, rules: Rules, handicap: Option<Handicap>) -> Game {
+        Game::with_settings(&GameSettings {
+            size,
+            game_type: GameType::Go(rules),
+            komi: None,
+            handicap,
+            difficulty: Default::default(),
+            opening: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn board_sync_tells_rules_and_plays_only_new_moves() {
+        let (mut engine, sent) = fake_engine("name\nkata-set-rules\nkgs-rules", &["=", "=", "=", "=", "=", "= C3", "=", "= pass"]);
+        assert_eq!(engine.name, "Fake 1.0");
+        assert_eq!(sent.take(), ["name", "version", "list_commands"]);
+
+        let mut game = go(9, Rules::TrompTaylor, None);
+        game.play(4, 4).unwrap();
+        assert_eq!(engine.genmove(&game).unwrap(), Some(Move::Place(2, 6)));
+        assert_eq!(
+            sent.take(),
+            ["boardsize 9", "clear_board", "kata-set-rules tromp-taylor", "komi 7.5", "play B E5", "genmove W"]
+        );
+
+        game.play(2, 6).unwrap();
+        game.play(6, 6).unwrap();
+        assert_eq!(engine.genmove(&game).unwrap(), Some(Move::Pass));
+        assert_eq!(sent.take(), ["play B G3", "genmove W"]);
+    }
+
+    #[test]
+    fn engine_without_rules_gets_compensation_in_komi() {
+        let (mut engine, sent) = fake_engine("name\ngenmove", &["=", "=", "=", "=", "= resign"]);
+        sent.take();
+        let game = go(19, Rules::Chinese, Some(Handicap::Fixed(2)));
+        assert_eq!(engine.genmove(&game).unwrap(), None);
+        assert_eq!(
+            sent.take(),
+            ["boardsize 19", "clear_board", "komi 2.5", "set_free_handicap Q16 D4", "genmove W"]
+        );
+    }
+
+    #[test]
+    fn error_replies_and_bad_moves() {
+        let (mut engine, _) = fake_engine("", &["? unknown command", "=5 Z99"]);
+        assert!(matches!(engine.send("kata-analyze"), Err(GtpError::Engine(message)) if message == "unknown command"));
+        let mut game = go(9, Rules::Japanese, None);
+        // Skips syncing, which the script has no answers for
+        engine.board = Some(EngineBoard {
+            size: 9,
+            rules: Some(Rules::Japanese),
+            komi: game.komi,
+            setup: Vec::new(),
+            moves: Vec::new(),
+        });
+        game.current_turn = Player::Black;
+        assert!(matches!(engine.genmove(&game), Err(GtpError::Protocol(_))));
+    }
+
+    // Feeds the script to a server on a 9x9 Chinese board and returns its answers
+    fn serve(script: &str) -> Vec<String> {
+        let mut server = GtpServer::new(GameSettings {
+            size: 9,
+            game_type: GameType::Go(Rules::Chinese),
+            komi: None,
+            handicap: None,
+            difficulty: Default::default(),
+            opening: None,
+        })
+        .unwrap();
+        let output = transcript(script, |input, output| server.run(input, output));
+        output.split_terminator("\n\n").map(str::to_string).collect()
+    }
+
+    #[test]
+    fn server_echoes_ids_and_reports_errors() {
+        let answers = serve("1 name\n  # comment only\n22 boardsize x\nfoo\n3 play black Z1\nplay\n4 known_command undo\n");
+        assert_eq!(answers, ["=1 go-game", "?22 syntax error", "? unknown command", "?3 syntax error", "? syntax error", "=4 true"]);
+    }
+
+    #[test]
+    fn server_handicap_needs_an_empty_board() {
+        let answers = serve("fixed_handicap 2\nfixed_handicap 2\nclear_board\nplay b e5\nfixed_handicap 2\nclear_board\nfixed_handicap 10\n");
+        assert_eq!(answers, ["= G7 C3", "? board not empty", "= ", "= ", "? board not empty", "= ", "? invalid number of stones"]);
+    }
+
+    #[test]
+    fn server_undo_and_final_score() {
+        let answers = serve("undo\nplay b e5\nplay w e5\nundo\nundo\nplay b e5\nfinal_score\n");
+        assert_eq!(answers, ["? cannot undo", "= ", "? illegal move", "= ", "? cannot undo", "= ", "= B+73.5"]);
+    }
+
+    #[test]
+    fn server_stops_at_quit() {
+        let answers = serve("name\n9 quit\nname\n");
+        assert_eq!(answers, ["= go-game", "=9 "]);
+    }
+
+    #[test]
+    fn dead_engine() {
+        let sent = Sent::default();
+        let start = GtpEngine::start(None, Box::new(sent.clone()), script(""));
+        assert!(matches!(start, Err(GtpError::Closed)));
+
+        let (mut engine, _) = fake_engine("", &["="]);
+        assert!(matches!(engine.genmove(&go(9, Rules::Chinese, None)), Err(GtpError::Closed)));
+    }
+}
@@ -0,0 +1,18 @@
+// Game logic and engines, shared by the app and the headless binaries in src/bin
+pub mod ai;
+pub mod connect6_ai;
+pub mod game;
+pub mod gomoku_ai;
+pub mod gtp;
+pub mod mcts;
+pub mod net;
+pub mod opening;
+pub mod piskvork;
+pub mod renju;
+pub mod rules;
+pub mod scoring;
+pub mod sgf;
+pub mod tree;
+
+#[cfg(test)]
+mod test_util;
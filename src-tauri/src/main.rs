@@ -1,31 +1,152 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use std::sync::Mutex;
-use tauri::{State, Window};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use tauri::{Manager, State, Window};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc::{Receiver, Sender};
 
-mod game;
-use game::{Game, Player, GameType};
+use go_game::{ai, game, gtp, net, opening, piskvork, rules, scoring, sgf, tree};
+use ai::{AiConfig, Difficulty, OpeningAction, SearchControl};
+use game::{Game, GamePhase, GameSettings, GameType, Move, Player, Prisoners};
+use gtp::{GtpEngine, GtpError};
+use net::{write_frame, FrameReader};
+use opening::{Opening, OpeningChoice, Seat};
+use piskvork::{PiskvorkEngine, PiskvorkError};
+use rules::Rules;
+use scoring::Score;
+use tree::{GameTree, Markup, MarkupKind};
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Serialize)]
 struct GameUpdate {
     board: Vec<Vec<Player>>,
     current_turn: Player,
-    last_move: Option<(usize, usize)>,
+    last_move: Option<Move>,
+    // Stones removed by the last move, so the frontend can animate them
+    captured: Vec<(usize, usize)>,
+    prisoners: Prisoners,
     winner: Option<Player>,
     is_draw: bool,
+    phase: GamePhase,
+    can_undo: bool,
+    can_redo: bool,
+    handicap_remaining: usize,
+    score: Option<Score>,
+    dead_stones: Vec<(usize, usize)>,
+    territory: Option<Vec<Vec<Player>>>,
+    opening: Option<Opening>,
+    // Who has to act next; the computer plays the second seat
+    to_act: Seat,
+    // Stones left in the current turn, two at the start of most Connect6 turns
+    stones_to_place: usize,
+}
+
+impl From<&Game> for GameUpdate {
+    fn from(game: &Game) -> Self {
+        GameUpdate {
+            board: game.board.clone(),
+            current_turn: game.current_turn,
+            last_move: game.last_move,
+            captured: game.last_captured.clone(),
+            prisoners: game.prisoners,
+            winner: game.winner,
+            is_draw: game.is_draw,
+            phase: game.phase,
+            can_undo: !game.moves.is_empty(),
+            can_redo: !game.redo_stack.is_empty(),
+            handicap_remaining: game.handicap_remaining,
+            score: (game.phase == GamePhase::Scoring).then(|| game.score()),
+            dead_stones: game.dead_stones.iter().copied().collect(),
+            territory: (game.phase == GamePhase::Scoring).then(|| game.territory_map()),
+            opening: game.opening.clone(),
+            to_act: game.seat_to_act(),
+            stones_to_place: game.stones_to_place(),
+        }
+    }
+}
+
+// Position in a loaded game record, for stepping through it and its variations
+#[derive(Clone, Serialize)]
+struct ReviewUpdate {
+    game: GameUpdate,
+    game_type: GameType,
+    comment: String,
+    markup: Vec<Markup>,
+    move_number: usize,
+    // Which of the alternatives for the current move is shown, and how many there are
+    variation: usize,
+    variations: usize,
+    // Whether every move up to here is the first choice, so there is nothing to promote
+    on_main_line: bool,
+    // Moves leading to each continuation, main line first
+    next_moves: Vec<Option<Move>>,
+}
+
+impl From<&GameTree> for ReviewUpdate {
+    fn from(tree: &GameTree) -> Self {
+        let (variation, variations) = tree.variation();
+        ReviewUpdate {
+            game: GameUpdate::from(tree.game()),
+            game_type: tree.game().game_type,
+            comment: tree.current().comment.clone(),
+            markup: tree.current().markup.clone(),
+            move_number: tree.move_number(),
+            variation,
+            variations,
+            on_main_line: tree.on_main_line(),
+            next_moves: tree.next_moves(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 enum NetworkMessage {
     Move(usize, usize),
+    // All stones of a turn, for Connect6
+    Turn(Vec<(usize, usize)>),
+    Pass,
     Resign,
     OfferDraw,
     AcceptDraw,
     RejectDraw,
-    Restart(usize, GameType),
+    Restart(GameSettings),
+    // Scoring phase: toggle a group dead/alive, accept the result, or dispute it
+    MarkDead(usize, usize),
+    AcceptScore,
+    ResumePlay,
+    // Taking back the last move needs the opponent's consent
+    RequestUndo(UndoRequest),
+    AcceptUndo(UndoRequest),
+    RejectUndo,
+    // A colour or other choice in a Gomoku opening; its stones travel as moves
+    OpeningChoice(OpeningChoice),
+}
+
+// Whose turn an undo request takes back, and how many moves the game had
+// when it was asked for
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct UndoRequest {
+    player: Player,
+    moves: usize,
+}
+
+impl UndoRequest {
+    // Takes back the requester's last turn, unless the game has moved on since
+    fn apply(&self, game: &mut Game) -> Result<(), String> {
+        if game.moves.len() != self.moves || game.moves.last().map(|r| r.player) != Some(self.player) {
+            return Err("The game has moved on since the undo was requested".to_string());
+        }
+        game.undo_turn()?;
+        Ok(())
+    }
+}
+
+// Both sides have to accept the dead stones before a networked result is final
+#[derive(Default)]
+struct ScoreAgreement {
+    local: bool,
+    remote: bool,
 }
 
 struct AppState {
@@ -36,19 +157,51 @@ struct AppState {
     // Let's just store if we are connected and let a background task handle incoming.
     // Outgoing moves can be sent via a channel or by cloning the stream (Arc<Mutex<TcpStream>>).
     tx: Mutex<Option<tokio::sync::mpsc::Sender<String>>>, 
+    score_agreement: Mutex<ScoreAgreement>,
+    // Game record being reviewed; its current position is mirrored into `game`
+    review: Mutex<Option<GameTree>>,
+    // Go search budget the player set over the difficulty's
+    ai_config: Mutex<AiConfig>,
+    // Set while the computer is thinking about a move
+    ai_search: Mutex<Option<Arc<SearchControl>>>,
+    // External engines that play Go and Gomoku instead of the built-in AI
+    gtp_engine: Mutex<Option<GtpEngine>>,
+    piskvork_engine: Mutex<Option<PiskvorkEngine>>,
+}
+
+// An external engine, lent to one search and handed back afterwards
+enum ExternalEngine {
+    Gtp(GtpEngine),
+    Piskvork(PiskvorkEngine),
+}
+
+impl ExternalEngine {
+    // The engine's moves, None if it resigned, and whether it is still usable
+    fn genmove(&mut self, game: &Game) -> (Result<Option<Vec<Move>>, String>, bool) {
+        match self {
+            ExternalEngine::Gtp(engine) => {
+                let reply = engine.genmove(game);
+                let alive = !matches!(reply, Err(GtpError::Io(_) | GtpError::Closed));
+                (reply.map(|mv| mv.map(|mv| vec![mv])).map_err(String::from), alive)
+            }
+            ExternalEngine::Piskvork(engine) => {
+                let reply = engine.genmove(game);
+                let alive = !matches!(reply, Err(PiskvorkError::Io(_) | PiskvorkError::Closed));
+                (reply.map(|(x, y)| Some(vec![Move::Place(x, y)])).map_err(String::from), alive)
+            }
+        }
+    }
 }
 
 #[tauri::command]
-fn new_game(state: State<AppState>, size: usize, game_type: GameType) -> GameUpdate {
+fn new_game(state: State<AppState>, settings: GameSettings) -> Result<GameUpdate, String> {
+    // Review before game, the order every command takes the two locks in
+    *state.review.lock().unwrap() = None;
     let mut game = state.game.lock().unwrap();
-    *game = Game::new(size, game_type);
-    GameUpdate {
-        board: game.board.clone(),
-        current_turn: game.current_turn,
-        last_move: game.last_move,
-        winner: game.winner,
-        is_draw: game.is_draw,
-    }
+    *game = Game::with_settings(&settings)?;
+    *state.score_agreement.lock().unwrap() = ScoreAgreement::default();
+    stop_search(&state, false);
+    Ok(GameUpdate::from(&*game))
 }
 
 #[tauri::command]
@@ -63,20 +216,14 @@ async fn play_move(
         // Apply move locally
         match game.play(x, y) {
             Ok(_) => {
-                let update = GameUpdate {
-                    board: game.board.clone(),
-                    current_turn: game.current_turn,
-                    last_move: game.last_move,
-                    winner: game.winner,
-                    is_draw: game.is_draw,
-                };
+                let update = GameUpdate::from(&*game);
                 
                 let tx_guard = state.tx.lock().unwrap();
                 let sender = tx_guard.clone();
                 
                 (Ok(update), sender)
             }
-            Err(e) => (Err(e), None),
+            Err(e) => (Err(e.to_string()), None),
         }
     };
 
@@ -91,12 +238,18 @@ async fn play_move(
 #[tauri::command]
 async fn handle_game_action(
     state: State<'_, AppState>,
-    action: String, // "resign", "offer_draw", "accept_draw", "reject_draw", "restart"
-    payload: Option<String> // For restart: "size,type"
+    action: String, // "resign", "offer_draw", "accept_draw", "reject_draw", "restart", "accept_score", "resume_play", "request_undo", "accept_undo", "reject_undo"
+    payload: Option<String> // For restart: GameSettings as JSON; for accept_undo: the UndoRequest
 ) -> Result<GameUpdate, String> {
+    // Leave review mode before the game lock is taken, in the same order as the review commands
+    if action == "restart" {
+        *state.review.lock().unwrap() = None;
+    }
     let (update, sender, msg_to_send) = {
         let mut game = state.game.lock().unwrap();
         let mut msg_to_send = None;
+        // Reported to our UI after the peer has been told
+        let mut error = None;
 
         match action.as_str() {
             "resign" => {
@@ -115,34 +268,63 @@ async fn handle_game_action(
             },
             "restart" => {
                 if let Some(p) = payload {
-                    // payload format: "size,type" e.g. "19,Go"
-                    let parts: Vec<&str> = p.split(',').collect();
-                    if parts.len() == 2 {
-                        let size = parts[0].parse().unwrap_or(19);
-                        let gtype = match parts[1] {
-                            "Gomoku" => GameType::Gomoku,
-                            _ => GameType::Go,
-                        };
-                        *game = Game::new(size, gtype);
-                        msg_to_send = Some(NetworkMessage::Restart(size, gtype));
+                    // payload format: {"size":19,"game_type":{"Go":"Japanese"},"komi":6.5,"handicap":{"Fixed":2}}
+                    let settings: GameSettings = serde_json::from_str(&p)
+                        .map_err(|e| format!("Invalid game settings: {}", e))?;
+                    *game = Game::with_settings(&settings)?;
+                    *state.score_agreement.lock().unwrap() = ScoreAgreement::default();
+                    stop_search(&state, false);
+                    msg_to_send = Some(NetworkMessage::Restart(settings));
+                }
+            },
+            "accept_score" => {
+                let mut agreement = state.score_agreement.lock().unwrap();
+                agreement.local = true;
+                // Offline there is nobody else who has to agree
+                if agreement.remote || state.tx.lock().unwrap().is_none() {
+                    game.finish_scoring()?;
+                }
+                msg_to_send = Some(NetworkMessage::AcceptScore);
+            },
+            "resume_play" => {
+                game.resume_play()?;
+                *state.score_agreement.lock().unwrap() = ScoreAgreement::default();
+                msg_to_send = Some(NetworkMessage::ResumePlay);
+            },
+            "request_undo" => {
+                // The turn to take back is the one the opponent has not answered yet
+                let player = game.moves.last().map(|r| r.player).ok_or("No move to undo")?;
+                msg_to_send = Some(NetworkMessage::RequestUndo(UndoRequest { player, moves: game.moves.len() }));
+            },
+            "accept_undo" => {
+                let request: UndoRequest = serde_json::from_str(payload.as_deref().unwrap_or_default())
+                    .map_err(|e| format!("Invalid undo request: {}", e))?;
+                match request.apply(&mut game) {
+                    Ok(()) => {
+                        *state.score_agreement.lock().unwrap() = ScoreAgreement::default();
+                        msg_to_send = Some(NetworkMessage::AcceptUndo(request));
+                    }
+                    Err(e) => {
+                        msg_to_send = Some(NetworkMessage::RejectUndo);
+                        error = Some(e);
                     }
                 }
             },
+            "reject_undo" => {
+                msg_to_send = Some(NetworkMessage::RejectUndo);
+            },
             _ => {}
         }
 
-        let update = GameUpdate {
-            board: game.board.clone(),
-            current_turn: game.current_turn,
-            last_move: game.last_move,
-            winner: game.winner,
-            is_draw: game.is_draw,
+        let update = match error {
+            Some(e) => Err(e),
+            None => Ok(GameUpdate::from(&*game)),
         };
         
         let tx_guard = state.tx.lock().unwrap();
         let sender = tx_guard.clone();
         
-        (Ok(update), sender, msg_to_send)
+        (update, sender, msg_to_send)
     };
 
     if let Some(s) = sender {
@@ -165,88 +347,534 @@ async fn apply_remote_move(
     
     match game.play(x, y) {
         Ok(_) => {
-            Ok(GameUpdate {
-                board: game.board.clone(),
-                current_turn: game.current_turn,
-                last_move: game.last_move,
-                winner: game.winner,
-                is_draw: game.is_draw,
-            })
+            Ok(GameUpdate::from(&*game))
         }
-        Err(e) => Err(e),
+        Err(e) => Err(e.to_string()),
     }
 }
 
+// Plays a whole turn at once, so the opponent never sees half of a Connect6 turn
 #[tauri::command]
-fn get_state(state: State<AppState>) -> GameUpdate {
-    let game = state.game.lock().unwrap();
-    GameUpdate {
-        board: game.board.clone(),
-        current_turn: game.current_turn,
-        last_move: game.last_move,
-        winner: game.winner,
-        is_draw: game.is_draw,
+async fn play_turn(state: State<'_, AppState>, stones: Vec<(usize, usize)>) -> Result<GameUpdate, String> {
+    let (update, sender) = {
+        let mut game = state.game.lock().unwrap();
+        game.play_turn(&stones)?;
+
+        let update = GameUpdate::from(&*game);
+        let sender = state.tx.lock().unwrap().clone();
+        (update, sender)
+    };
+
+    if let Some(s) = sender {
+        let msg = serde_json::to_string(&NetworkMessage::Turn(stones)).unwrap();
+        let _ = s.send(msg).await;
     }
+
+    Ok(update)
 }
 
 #[tauri::command]
-async fn play_ai(state: State<'_, AppState>) -> Result<GameUpdate, String> {
+async fn apply_remote_turn(state: State<'_, AppState>, stones: Vec<(usize, usize)>) -> Result<GameUpdate, String> {
     let mut game = state.game.lock().unwrap();
-    
-    if let Some((x, y)) = game.get_ai_move() {
-        if game.play(x, y).is_ok() {
-             return Ok(GameUpdate {
-                board: game.board.clone(),
-                current_turn: game.current_turn,
-                last_move: game.last_move,
-                winner: game.winner,
-                is_draw: game.is_draw,
+    game.play_turn(&stones)?;
+    Ok(GameUpdate::from(&*game))
+}
+
+#[tauri::command]
+async fn choose_opening(state: State<'_, AppState>, choice: OpeningChoice) -> Result<GameUpdate, String> {
+    let (update, sender) = {
+        let mut game = state.game.lock().unwrap();
+        game.choose_opening(choice)?;
+
+        let update = GameUpdate::from(&*game);
+        let sender = state.tx.lock().unwrap().clone();
+        (update, sender)
+    };
+
+    if let Some(s) = sender {
+        let msg = serde_json::to_string(&NetworkMessage::OpeningChoice(choice)).unwrap();
+        let _ = s.send(msg).await;
+    }
+
+    Ok(update)
+}
+
+#[tauri::command]
+async fn apply_remote_opening_choice(state: State<'_, AppState>, choice: OpeningChoice) -> Result<GameUpdate, String> {
+    let mut game = state.game.lock().unwrap();
+    game.choose_opening(choice)?;
+    Ok(GameUpdate::from(&*game))
+}
+
+#[tauri::command]
+async fn pass(state: State<'_, AppState>) -> Result<GameUpdate, String> {
+    let (update, sender) = {
+        let mut game = state.game.lock().unwrap();
+        game.pass()?;
+
+        let update = GameUpdate::from(&*game);
+        let sender = state.tx.lock().unwrap().clone();
+        (update, sender)
+    };
+
+    if let Some(s) = sender {
+        let msg = serde_json::to_string(&NetworkMessage::Pass).unwrap();
+        let _ = s.send(msg).await;
+    }
+
+    Ok(update)
+}
+
+#[tauri::command]
+async fn apply_remote_pass(state: State<'_, AppState>) -> Result<GameUpdate, String> {
+    let mut game = state.game.lock().unwrap();
+    game.pass()?;
+    Ok(GameUpdate::from(&*game))
+}
+
+#[tauri::command]
+async fn toggle_dead_stone(
+    state: State<'_, AppState>,
+    x: usize,
+    y: usize
+) -> Result<GameUpdate, String> {
+    let (update, sender) = {
+        let mut game = state.game.lock().unwrap();
+        game.toggle_dead(x, y)?;
+        // Any change to the marks invalidates earlier acceptance
+        *state.score_agreement.lock().unwrap() = ScoreAgreement::default();
+
+        let update = GameUpdate::from(&*game);
+        let sender = state.tx.lock().unwrap().clone();
+        (update, sender)
+    };
+
+    if let Some(s) = sender {
+        let msg = serde_json::to_string(&NetworkMessage::MarkDead(x, y)).unwrap();
+        let _ = s.send(msg).await;
+    }
+
+    Ok(update)
+}
+
+#[tauri::command]
+async fn apply_remote_dead_stone(
+    state: State<'_, AppState>,
+    x: usize,
+    y: usize
+) -> Result<GameUpdate, String> {
+    let mut game = state.game.lock().unwrap();
+    game.toggle_dead(x, y)?;
+    *state.score_agreement.lock().unwrap() = ScoreAgreement::default();
+    Ok(GameUpdate::from(&*game))
+}
+
+#[tauri::command]
+async fn apply_remote_score_action(
+    state: State<'_, AppState>,
+    action: String // "accept_score", "resume_play"
+) -> Result<GameUpdate, String> {
+    let mut game = state.game.lock().unwrap();
+    let mut agreement = state.score_agreement.lock().unwrap();
+
+    match action.as_str() {
+        "accept_score" => {
+            agreement.remote = true;
+            if agreement.local {
+                game.finish_scoring()?;
+            }
+        },
+        "resume_play" => {
+            game.resume_play()?;
+            *agreement = ScoreAgreement::default();
+        },
+        _ => return Err(format!("Unknown scoring action: {}", action)),
+    }
+
+    Ok(GameUpdate::from(&*game))
+}
+
+#[tauri::command]
+fn undo(state: State<AppState>) -> Result<GameUpdate, String> {
+    if state.tx.lock().unwrap().is_some() {
+        return Err("Ask your opponent to accept an undo in network games".to_string());
+    }
+    let mut game = state.game.lock().unwrap();
+    game.undo_turn()?;
+    *state.score_agreement.lock().unwrap() = ScoreAgreement::default();
+    Ok(GameUpdate::from(&*game))
+}
+
+#[tauri::command]
+fn redo(state: State<AppState>) -> Result<GameUpdate, String> {
+    if state.tx.lock().unwrap().is_some() {
+        return Err("Redo is not available in network games".to_string());
+    }
+    let mut game = state.game.lock().unwrap();
+    game.redo_turn()?;
+    Ok(GameUpdate::from(&*game))
+}
+
+// The opponent accepted our undo request
+#[tauri::command]
+async fn apply_remote_undo(state: State<'_, AppState>, request: UndoRequest) -> Result<GameUpdate, String> {
+    let mut game = state.game.lock().unwrap();
+    request.apply(&mut game)?;
+    *state.score_agreement.lock().unwrap() = ScoreAgreement::default();
+    Ok(GameUpdate::from(&*game))
+}
+
+#[tauri::command]
+fn export_sgf(
+    state: State<AppState>,
+    path: String,
+    black: Option<String>,
+    white: Option<String>
+) -> Result<(), String> {
+    let black = black.as_deref().unwrap_or("Black");
+    let white = white.as_deref().unwrap_or("White");
+    // In review mode the whole tree is saved, not just the line on the board
+    let record = match &*state.review.lock().unwrap() {
+        Some(tree) => sgf::export_tree(tree, black, white),
+        None => sgf::export(&state.game.lock().unwrap(), black, white),
+    };
+    std::fs::write(&path, record).map_err(|e| format!("Failed to write {}: {}", path, e))
+}
+
+#[tauri::command]
+fn load_sgf(state: State<AppState>, path: String) -> Result<ReviewUpdate, String> {
+    if state.tx.lock().unwrap().is_some() {
+        return Err("Game records cannot be loaded during a network game".to_string());
+    }
+    let record = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let tree = GameTree::from_sgf(&record).map_err(|e| e.to_string())?;
+
+    *state.game.lock().unwrap() = tree.game().clone();
+    *state.score_agreement.lock().unwrap() = ScoreAgreement::default();
+    let update = ReviewUpdate::from(&tree);
+    *state.review.lock().unwrap() = Some(tree);
+    Ok(update)
+}
+
+// Reviews the game played so far; moves at earlier positions then become variations
+#[tauri::command]
+fn start_review(state: State<AppState>) -> Result<ReviewUpdate, String> {
+    if state.tx.lock().unwrap().is_some() {
+        return Err("Review is not available during a network game".to_string());
+    }
+    let mut review = state.review.lock().unwrap();
+    if review.is_none() {
+        let game = state.game.lock().unwrap();
+        let mut tree = GameTree::from_game(&game);
+        tree.go_to_end();
+        *review = Some(tree);
+    }
+    Ok(ReviewUpdate::from(review.as_ref().unwrap()))
+}
+
+// Runs an edit or a navigation step on the reviewed tree and shows its new position
+fn update_review<F>(state: &AppState, f: F) -> Result<ReviewUpdate, String>
+where
+    F: FnOnce(&mut GameTree) -> Result<(), String>,
+{
+    let mut review = state.review.lock().unwrap();
+    let tree = review.as_mut().ok_or("Not in review mode")?;
+    f(tree)?;
+    *state.game.lock().unwrap() = tree.game().clone();
+    Ok(ReviewUpdate::from(&*tree))
+}
+
+#[tauri::command]
+fn navigate_review(state: State<AppState>, action: String) -> Result<ReviewUpdate, String> {
+    update_review(&state, |tree| {
+        match action.as_str() {
+            "forward" => {
+                tree.forward();
+            }
+            "back" => {
+                tree.back();
+            }
+            "start" => tree.go_to_start(),
+            "end" => tree.go_to_end(),
+            _ => return Err(format!("Unknown review action: {}", action)),
+        }
+        Ok(())
+    })
+}
+
+#[tauri::command]
+fn switch_variation(state: State<AppState>, index: usize) -> Result<ReviewUpdate, String> {
+    update_review(&state, |tree| {
+        if tree.switch_branch(index) {
+            Ok(())
+        } else {
+            Err(format!("There is no variation {}", index + 1))
+        }
+    })
+}
+
+#[tauri::command]
+fn review_play(state: State<AppState>, x: usize, y: usize) -> Result<ReviewUpdate, String> {
+    update_review(&state, |tree| Ok(tree.play(Move::Place(x, y))?))
+}
+
+#[tauri::command]
+fn review_pass(state: State<AppState>) -> Result<ReviewUpdate, String> {
+    update_review(&state, |tree| Ok(tree.play(Move::Pass)?))
+}
+
+#[tauri::command]
+fn delete_variation(state: State<AppState>) -> Result<ReviewUpdate, String> {
+    update_review(&state, |tree| {
+        if tree.delete_variation() {
+            Ok(())
+        } else {
+            Err("The starting position cannot be deleted".to_string())
+        }
+    })
+}
+
+#[tauri::command]
+fn promote_variation(state: State<AppState>) -> Result<ReviewUpdate, String> {
+    update_review(&state, |tree| {
+        if tree.promote_variation() {
+            Ok(())
+        } else {
+            Err("This position is already on the main line".to_string())
+        }
+    })
+}
+
+#[tauri::command]
+fn set_review_comment(state: State<AppState>, comment: String) -> Result<ReviewUpdate, String> {
+    update_review(&state, |tree| {
+        tree.set_comment(comment);
+        Ok(())
+    })
+}
+
+#[tauri::command]
+fn toggle_markup(state: State<AppState>, x: usize, y: usize, kind: MarkupKind) -> Result<ReviewUpdate, String> {
+    update_review(&state, |tree| Ok(tree.toggle_markup((x, y), kind)?))
+}
+
+// Leaves review mode, play continues from the position on the board
+#[tauri::command]
+fn close_review(state: State<AppState>) -> GameUpdate {
+    *state.review.lock().unwrap() = None;
+    let game = state.game.lock().unwrap();
+    GameUpdate::from(&*game)
+}
+
+#[tauri::command]
+fn get_state(state: State<AppState>) -> GameUpdate {
+    let game = state.game.lock().unwrap();
+    GameUpdate::from(&*game)
+}
+
+#[tauri::command]
+async fn play_ai(state: State<'_, AppState>, window: Window) -> Result<GameUpdate, String> {
+    // Search on a snapshot so the board stays usable while the engine thinks
+    let snapshot = state.game.lock().unwrap().clone();
+    let (moves_played, position_hash) = (snapshot.moves.len(), snapshot.position_hash);
+    let control = Arc::new(SearchControl::default());
+    {
+        let mut search = state.ai_search.lock().unwrap();
+        if search.is_some() {
+            return Err("The AI is already thinking".to_string());
+        }
+        *search = Some(control.clone());
+    }
+
+    if snapshot.phase == GamePhase::Opening {
+        return play_ai_opening(&state, snapshot, control).await;
+    }
+
+    let engine = match snapshot.game_type {
+        GameType::Go(_) if snapshot.phase == GamePhase::Playing => {
+            state.gtp_engine.lock().unwrap().take().map(ExternalEngine::Gtp)
+        }
+        GameType::Gomoku(_) | GameType::Renju => state.piskvork_engine.lock().unwrap().take().map(ExternalEngine::Piskvork),
+        _ => None,
+    };
+
+    let strength = state.ai_config.lock().unwrap().apply(snapshot.difficulty.strength());
+    let search = control.clone();
+    let result = tauri::async_runtime::spawn_blocking(move || match engine {
+        // An external engine cannot be interrupted; a cancelled reply is dropped below
+        Some(mut engine) => {
+            let (reply, alive) = engine.genmove(&snapshot);
+            (reply, alive.then_some(engine))
+        }
+        None => {
+            let moves = ai::choose_turn(&snapshot, &strength, &search.stop, &mut |progress| {
+                let _ = window.emit("ai-progress", progress);
             });
+            (moves.map(Some).ok_or_else(|| "AI could not find a move".to_string()), None)
+        }
+    })
+    .await;
+    *state.ai_search.lock().unwrap() = None;
+    let (reply, engine) = result.map_err(|e| format!("AI failed: {}", e))?;
+    match engine {
+        Some(ExternalEngine::Gtp(engine)) => *state.gtp_engine.lock().unwrap() = Some(engine),
+        Some(ExternalEngine::Piskvork(engine)) => *state.piskvork_engine.lock().unwrap() = Some(engine),
+        None => {}
+    }
+    let moves = reply?;
+    if control.cancelled.load(Ordering::Relaxed) {
+        return Err("The AI was cancelled".to_string());
+    }
+
+    let mut game = state.game.lock().unwrap();
+    if game.moves.len() != moves_played || game.position_hash != position_hash {
+        return Err("The game changed while the AI was thinking".to_string());
+    }
+
+    match moves {
+        Some(moves) => {
+            for mv in moves {
+                if game.winner.is_some() {
+                    break;
+                }
+                match mv {
+                    Move::Place(x, y) => {
+                        game.play(x, y)?;
+                    }
+                    Move::Pass => game.pass()?,
+                }
+            }
+        }
+        // Only GTP engines resign
+        None => game.winner = Some(game.current_turn.other()),
+    }
+    Ok(GameUpdate::from(&*game))
+}
+
+// External engines know nothing of opening protocols, so the built-in AI
+// plays the opening whichever engine takes over afterwards
+async fn play_ai_opening(state: &AppState, snapshot: Game, control: Arc<SearchControl>) -> Result<GameUpdate, String> {
+    let (moves_played, opening) = (snapshot.moves.len(), snapshot.opening.clone());
+    let search = control.clone();
+    let result = tauri::async_runtime::spawn_blocking(move || ai::choose_opening_action(&snapshot, &search.stop)).await;
+    *state.ai_search.lock().unwrap() = None;
+    let action = result
+        .map_err(|e| format!("AI failed: {}", e))?
+        .ok_or_else(|| "AI could not find a move".to_string())?;
+    if control.cancelled.load(Ordering::Relaxed) {
+        return Err("The AI was cancelled".to_string());
+    }
+
+    let mut game = state.game.lock().unwrap();
+    if game.moves.len() != moves_played || game.opening != opening {
+        return Err("The game changed while the AI was thinking".to_string());
+    }
+    match action {
+        OpeningAction::Place(x, y) => {
+            game.play(x, y)?;
         }
+        OpeningAction::Choose(choice) => game.choose_opening(choice)?,
     }
+    Ok(GameUpdate::from(&*game))
+}
 
-    Err("AI could not find a move".to_string())
+// Starts an external GTP engine, such as "gnugo --mode gtp", to play Go in
+// place of the built-in AI. Returns the engine's name and version.
+#[tauri::command]
+async fn start_gtp_engine(state: State<'_, AppState>, command_line: String) -> Result<String, String> {
+    ensure_ai_idle(&state)?;
+    let (program, args) = split_command_line(&command_line)?;
+    let engine = tauri::async_runtime::spawn_blocking(move || GtpEngine::spawn(&program, &args))
+        .await
+        .map_err(|e| format!("Engine failed to start: {}", e))??;
+    let name = engine.name.clone();
+    *state.gtp_engine.lock().unwrap() = Some(engine);
+    Ok(name)
+}
+
+// Shuts the external engine down and goes back to the built-in AI
+#[tauri::command]
+fn stop_gtp_engine(state: State<AppState>) -> Result<(), String> {
+    ensure_ai_idle(&state)?;
+    *state.gtp_engine.lock().unwrap() = None;
+    Ok(())
+}
+
+// Starts a Gomocup brain, such as "pbrain-embryo", to play Gomoku in place of
+// the built-in AI. Returns the brain's name and version.
+#[tauri::command]
+async fn start_piskvork_engine(state: State<'_, AppState>, command_line: String) -> Result<String, String> {
+    ensure_ai_idle(&state)?;
+    let (program, args) = split_command_line(&command_line)?;
+    let engine = tauri::async_runtime::spawn_blocking(move || PiskvorkEngine::spawn(&program, &args))
+        .await
+        .map_err(|e| format!("Engine failed to start: {}", e))??;
+    let name = engine.name.clone();
+    *state.piskvork_engine.lock().unwrap() = Some(engine);
+    Ok(name)
+}
+
+#[tauri::command]
+fn stop_piskvork_engine(state: State<AppState>) -> Result<(), String> {
+    ensure_ai_idle(&state)?;
+    *state.piskvork_engine.lock().unwrap() = None;
+    Ok(())
+}
+
+// Engines are lent to the search while it runs, so they cannot be swapped then
+fn ensure_ai_idle(state: &AppState) -> Result<(), String> {
+    if state.ai_search.lock().unwrap().is_some() {
+        return Err("The AI is thinking".to_string());
+    }
+    Ok(())
+}
+
+// Program and arguments, split on whitespace
+fn split_command_line(command_line: &str) -> Result<(String, Vec<String>), String> {
+    let mut words = command_line.split_whitespace();
+    let program = words.next().ok_or("No engine command given")?.to_string();
+    Ok((program, words.map(String::from).collect()))
+}
+
+// Interrupts the running search, if any. With `move_now` the AI still plays
+// the best move it has found so far.
+#[tauri::command]
+fn cancel_ai(state: State<AppState>, move_now: bool) {
+    stop_search(&state, move_now);
+}
+
+fn stop_search(state: &AppState, move_now: bool) {
+    if let Some(control) = state.ai_search.lock().unwrap().as_ref() {
+        control.cancelled.store(!move_now, Ordering::Relaxed);
+        control.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+#[tauri::command]
+fn set_ai_config(state: State<AppState>, config: AiConfig) {
+    *state.ai_config.lock().unwrap() = config;
+}
+
+#[tauri::command]
+fn set_difficulty(state: State<AppState>, difficulty: Difficulty) -> GameUpdate {
+    let mut game = state.game.lock().unwrap();
+    game.difficulty = difficulty;
+    GameUpdate::from(&*game)
 }
 
 #[tauri::command]
 async fn start_host(state: State<'_, AppState>, window: Window, port: u16) -> Result<String, String> {
     let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await.map_err(|e| e.to_string())?;
     
-    let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(10);
-    *state.tx.lock().unwrap() = Some(tx);
+    let (tx, rx) = tokio::sync::mpsc::channel::<String>(10);
+    *state.tx.lock().unwrap() = Some(tx.clone());
 
     tauri::async_runtime::spawn(async move {
-        if let Ok((mut socket, _)) = listener.accept().await {
-            let (mut reader, mut writer) = socket.split();
-            
-            // Reader task
-            let window_clone = window.clone();
-            let mut buf = [0; 1024];
-            
-            loop {
-                tokio::select! {
-                    // Read from network
-                    n = reader.read(&mut buf) => {
-                        match n {
-                            Ok(0) => break, // Connection closed
-                            Ok(n) => {
-                                let msg_str = String::from_utf8_lossy(&buf[0..n]);
-                                // Try parsing as NetworkMessage
-                                if let Ok(msg) = serde_json::from_str::<NetworkMessage>(&msg_str) {
-                                    window_clone.emit("network-action", msg).unwrap();
-                                } else if let Ok((x, y)) = serde_json::from_str::<(usize, usize)>(&msg_str) {
-                                    // Backward compatibility or fallback
-                                    window_clone.emit("network-action", NetworkMessage::Move(x, y)).unwrap();
-                                }
-                            }
-                            Err(_) => break,
-                        }
-                    }
-                    // Write to network
-                    Some(msg) = rx.recv() => {
-                        let _ = writer.write_all(msg.as_bytes()).await;
-                    }
-                }
+        match listener.accept().await {
+            Ok((socket, _)) => run_connection(socket, window, tx, rx).await,
+            Err(e) => {
+                let _ = window.emit("network-error", format!("Network error: {}", e));
+                disconnect(&window, &tx);
             }
         }
     });
@@ -258,51 +886,110 @@ async fn start_host(state: State<'_, AppState>, window: Window, port: u16) -> Re
 async fn connect_to_host(state: State<'_, AppState>, window: Window, ip: String) -> Result<String, String> {
     let socket = TcpStream::connect(ip).await.map_err(|e| e.to_string())?;
     
-    let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(10);
-    *state.tx.lock().unwrap() = Some(tx);
+    let (tx, rx) = tokio::sync::mpsc::channel::<String>(10);
+    *state.tx.lock().unwrap() = Some(tx.clone());
 
-    let (mut reader, mut writer) = socket.into_split();
+    tauri::async_runtime::spawn(run_connection(socket, window, tx, rx));
 
-    tauri::async_runtime::spawn(async move {
-        let mut buf = [0; 1024];
-        loop {
-            tokio::select! {
-                n = reader.read(&mut buf) => {
-                    match n {
-                        Ok(0) => break,
-                        Ok(n) => {
-                            let msg_str = String::from_utf8_lossy(&buf[0..n]);
-                            if let Ok(msg) = serde_json::from_str::<NetworkMessage>(&msg_str) {
-                                window.emit("network-action", msg).unwrap();
-                            } else if let Ok((x, y)) = serde_json::from_str::<(usize, usize)>(&msg_str) {
-                                window.emit("network-action", NetworkMessage::Move(x, y)).unwrap();
-                            }
-                        }
-                        Err(_) => break,
+    Ok("Connected".to_string())
+}
+
+// Passes messages between the peer and the UI until the connection closes or
+// breaks; what went wrong is reported to the UI as "network-error", and the
+// end of the connection as "network-disconnected". `tx` is the sender of `rx`
+// as stored in the app state.
+async fn run_connection(socket: TcpStream, window: Window, tx: Sender<String>, mut rx: Receiver<String>) {
+    let (reader, mut writer) = socket.into_split();
+    let mut frames = FrameReader::new(reader);
+    loop {
+        tokio::select! {
+            frame = frames.next_frame() => match frame {
+                Ok(Some(frame)) => match serde_json::from_slice::<NetworkMessage>(&frame) {
+                    Ok(msg) => {
+                        let _ = window.emit("network-action", msg);
+                    }
+                    // The frame was read whole, so the next one can still be understood
+                    Err(e) => {
+                        let _ = window.emit("network-error", format!("Unreadable network message: {}", e));
                     }
+                },
+                Ok(None) => break,
+                Err(e) => {
+                    let _ = window.emit("network-error", e.to_string());
+                    break;
                 }
-                Some(msg) = rx.recv() => {
-                    let _ = writer.write_all(msg.as_bytes()).await;
+            },
+            Some(msg) = rx.recv() => {
+                if let Err(e) = write_frame(&mut writer, msg.as_bytes()).await {
+                    let _ = window.emit("network-error", e.to_string());
+                    break;
                 }
             }
         }
-    });
+    }
+    disconnect(&window, &tx);
+}
 
-    Ok("Connected".to_string())
+// Forgets the connection's sender, unless a newer connection has replaced it,
+// and tells the UI the game is no longer online
+fn disconnect(window: &Window, tx: &Sender<String>) {
+    let state = window.state::<AppState>();
+    let mut current = state.tx.lock().unwrap();
+    if current.as_ref().is_some_and(|current| current.same_channel(tx)) {
+        *current = None;
+        let _ = window.emit("network-disconnected", ());
+    }
 }
 
 fn main() {
     tauri::Builder::default()
         .manage(AppState {
-            game: Mutex::new(Game::new(19, GameType::Go)),
+            game: Mutex::new(Game::new(19, GameType::Go(Rules::Chinese))),
             tx: Mutex::new(None),
+            score_agreement: Mutex::new(ScoreAgreement::default()),
+            review: Mutex::new(None),
+            ai_config: Mutex::new(AiConfig::default()),
+            ai_search: Mutex::new(None),
+            gtp_engine: Mutex::new(None),
+            piskvork_engine: Mutex::new(None),
         })
         .invoke_handler(tauri::generate_handler![
             new_game,
             play_move,
             apply_remote_move,
+            play_turn,
+            apply_remote_turn,
+            choose_opening,
+            apply_remote_opening_choice,
+            pass,
+            apply_remote_pass,
+            toggle_dead_stone,
+            apply_remote_dead_stone,
+            apply_remote_score_action,
+            undo,
+            redo,
+            apply_remote_undo,
+            export_sgf,
+            load_sgf,
+            start_review,
+            navigate_review,
+            switch_variation,
+            review_play,
+            review_pass,
+            delete_variation,
+            promote_variation,
+            set_review_comment,
+            toggle_markup,
+            close_review,
             get_state,
             play_ai,
+            cancel_ai,
+            start_gtp_engine,
+            stop_gtp_engine,
+            start_piskvork_engine,
+            stop_piskvork_engine,
+            set_ai_config,
+            set_difficulty,
             start_host,
             connect_to_host,
             handle_game_action
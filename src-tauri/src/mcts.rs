@@ -0,0 +1,562 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use rand::rngs::ThreadRng;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::ai::{Progress, PROGRESS_INTERVAL};
+use crate::game::{Game, GamePhase, GameType, Move, Player};
+
+// UCT exploration constant, close to sqrt(2)
+const EXPLORATION: f32 = 1.4;
+// Boards this size and up keep the tree off the first two lines in the
+// opening, which lasts this many moves per line of the board
+const LOW_LINE_BOARD: usize = 13;
+const OPENING_MOVES_PER_LINE: usize = 2;
+
+// How much the Go engine may think about one move
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct MctsConfig {
+    pub playouts: usize,
+    // Wall-clock limit; the search stops at whichever limit is reached first
+    pub time_limit_ms: Option<u64>,
+}
+
+// What the search found out about one move at the root
+#[derive(Clone, Copy, Debug)]
+pub struct MoveStats {
+    pub mv: Move,
+    pub visits: u32,
+}
+
+struct Node {
+    mv: Option<Move>,
+    // The player who made `mv`; wins are counted from their side
+    player: Player,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    untried: Vec<Move>,
+    visits: u32,
+    wins: f32,
+}
+
+impl Node {
+    fn new(mv: Option<Move>, player: Player, parent: Option<usize>, untried: Vec<Move>) -> Self {
+        Node {
+            mv,
+            player,
+            parent,
+            children: Vec::new(),
+            untried,
+            visits: 0,
+            wins: 0.0,
+        }
+    }
+}
+
+// Runs UCT over light random playouts for the player to move and returns
+// the root moves, most visited first
+pub fn search(
+    game: &Game,
+    config: &MctsConfig,
+    stop: &AtomicBool,
+    report: &mut dyn FnMut(Progress),
+) -> Vec<MoveStats> {
+    let mut rng = rand::thread_rng();
+    let mut nodes = vec![Node::new(None, game.current_turn.other(), None, candidate_moves(game))];
+    let started = Instant::now();
+    let time_limit = config.time_limit_ms.map(Duration::from_millis);
+    let mut last_report = started;
+
+    for playout in 0..config.playouts {
+        if time_limit.is_some_and(|limit| started.elapsed() >= limit) || stop.load(Ordering::Relaxed) {
+            break;
+        }
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            last_report = Instant::now();
+            report(progress(&nodes, playout as u64));
+        }
+
+        // Selection: follow the best child while the node is fully expanded
+        let mut state = game.clone();
+        let mut index = 0;
+        while nodes[index].untried.is_empty() && !nodes[index].children.is_empty() {
+            index = select_child(&nodes, index);
+            // Legal when the child was created, and the path to it is the same
+            let _ = apply(&mut state, nodes[index].mv.unwrap());
+        }
+
+        // Expansion: add one untried move, dropping the ones that turn out illegal
+        while !nodes[index].untried.is_empty() {
+            let i = rng.gen_range(0..nodes[index].untried.len());
+            let mv = nodes[index].untried.swap_remove(i);
+            let mover = state.current_turn;
+            if apply(&mut state, mv).is_ok() {
+                let child = nodes.len();
+                nodes.push(Node::new(Some(mv), mover, Some(index), candidate_moves(&state)));
+                nodes[index].children.push(child);
+                index = child;
+                break;
+            }
+        }
+
+        // In Atari Go the tree itself reaches positions that are already decided
+        let winner = match state.winner {
+            Some(winner) => winner,
+            None => Playout::new(&state).run(&mut rng),
+        };
+
+        // Backpropagation
+        let mut node = Some(index);
+        while let Some(i) = node {
+            nodes[i].visits += 1;
+            if nodes[i].player == winner {
+                nodes[i].wins += 1.0;
+            }
+            node = nodes[i].parent;
+        }
+    }
+
+    let mut stats: Vec<MoveStats> = nodes[0]
+        .children
+        .iter()
+        .map(|&child| {
+            MoveStats {
+                mv: nodes[child].mv.unwrap(),
+                visits: nodes[child].visits,
+            }
+        })
+        .collect();
+    stats.sort_by_key(|s| std::cmp::Reverse(s.visits));
+    stats
+}
+
+// The most visited move so far and how often the playouts through it were won
+fn progress(nodes: &[Node], playouts: u64) -> Progress {
+    let best = nodes[0].children.iter().map(|&child| &nodes[child]).max_by_key(|node| node.visits);
+    Progress {
+        best_move: best.and_then(|node| node.mv),
+        nodes: playouts,
+        win_rate: best.map_or(0.5, |node| node.wins / node.visits.max(1) as f32),
+    }
+}
+
+fn select_child(nodes: &[Node], index: usize) -> usize {
+    let parent_visits = (nodes[index].visits.max(1) as f32).ln();
+    let uct = |child: usize| {
+        let node = &nodes[child];
+        let visits = node.visits.max(1) as f32;
+        node.wins / visits + EXPLORATION * (parent_visits / visits).sqrt()
+    };
+    *nodes[index]
+        .children
+        .iter()
+        .max_by(|&&a, &&b| uct(a).total_cmp(&uct(b)))
+        .unwrap()
+}
+
+fn apply(game: &mut Game, mv: Move) -> Result<(), crate::game::GameError> {
+    match mv {
+        Move::Place(x, y) => game.play(x, y).map(|_| ()),
+        Move::Pass => game.pass(),
+    }
+}
+
+// Empty points that do not fill one of our own eyes. Passing is only
+// considered once the opponent has passed or nothing else is left, and
+// never in Atari Go, where an eye is filled rather than passing.
+// Light playouts cannot tell that the first two lines are too low in the
+// opening, so on big boards those points are left out then unless a stone
+// is already nearby.
+fn candidate_moves(game: &Game) -> Vec<Move> {
+    if game.phase != GamePhase::Playing || game.winner.is_some() {
+        return Vec::new();
+    }
+    let mut playout = Playout::new(game);
+    let point = |i: usize| Move::Place(i % game.size, i / game.size);
+    if playout.first_capture {
+        // Taking a stone wins on the spot, so nothing else is worth looking at
+        let captures = playout.atari_points(game.current_turn.other());
+        if !captures.is_empty() {
+            return captures.into_iter().map(point).collect();
+        }
+    }
+    let empty = |i: &usize| playout.board[*i] == Player::None;
+    let mut points: Vec<usize> = (0..game.size * game.size)
+        .filter(empty)
+        .filter(|&i| !playout.is_eye(i, game.current_turn))
+        .collect();
+    let opening = game.size >= LOW_LINE_BOARD && game.moves.len() < OPENING_MOVES_PER_LINE * game.size;
+    if opening && !playout.first_capture {
+        let high: Vec<usize> = points.iter().copied().filter(|&i| !playout.low_and_alone(i)).collect();
+        if !high.is_empty() {
+            points = high;
+        }
+    }
+    let mut moves: Vec<Move> = points.into_iter().map(point).collect();
+    if playout.first_capture {
+        if moves.is_empty() {
+            moves = (0..game.size * game.size).filter(empty).map(point).collect();
+        }
+    } else if moves.is_empty() || game.last_move == Some(Move::Pass) {
+        moves.push(Move::Pass);
+    }
+    moves
+}
+
+// Flat board for fast random games. Only simple ko is tracked and suicide is never played.
+struct Playout {
+    size: usize,
+    board: Vec<Player>,
+    to_move: Player,
+    ko: Option<usize>,
+    komi: f32,
+    // Atari Go: the first capture wins, so captures and escapes from atari come before random moves
+    first_capture: bool,
+    // Empty points in no particular order, and where each one sits in that list
+    empties: Vec<usize>,
+    empty_index: Vec<usize>,
+    // Flood fill scratch space: a point is visited when mark[i] == stamp
+    mark: Vec<u32>,
+    stamp: u32,
+    group: Vec<usize>,
+}
+
+impl Playout {
+    fn new(game: &Game) -> Self {
+        let size = game.size;
+        let board: Vec<Player> = game.board.iter().flatten().copied().collect();
+        let empties: Vec<usize> = (0..board.len()).filter(|&i| board[i] == Player::None).collect();
+        let mut empty_index = vec![0; board.len()];
+        for (k, &i) in empties.iter().enumerate() {
+            empty_index[i] = k;
+        }
+        Playout {
+            size,
+            board,
+            to_move: game.current_turn,
+            ko: None,
+            komi: game.komi + game.rules.handicap_compensation.points(game.handicap),
+            first_capture: game.game_type == GameType::AtariGo,
+            empties,
+            empty_index,
+            mark: vec![0; size * size],
+            stamp: 0,
+            group: Vec::new(),
+        }
+    }
+
+    fn set(&mut self, i: usize, player: Player) {
+        if player == Player::None {
+            self.empty_index[i] = self.empties.len();
+            self.empties.push(i);
+        } else {
+            let k = self.empty_index[i];
+            let last = *self.empties.last().unwrap();
+            self.empties.swap_remove(k);
+            if last != i {
+                self.empty_index[last] = k;
+            }
+        }
+        self.board[i] = player;
+    }
+
+    fn neighbors(&self, i: usize) -> impl Iterator<Item = usize> {
+        let size = self.size;
+        let (x, y) = (i % size, i / size);
+        [
+            (x > 0).then(|| i - 1),
+            (x + 1 < size).then(|| i + 1),
+            (y > 0).then(|| i - size),
+            (y + 1 < size).then(|| i + size),
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    // An empty point surrounded by the player's stones, with too few enemy
+    // stones on the diagonals to make it false
+    fn is_eye(&self, i: usize, player: Player) -> bool {
+        if self.neighbors(i).any(|n| self.board[n] != player) {
+            return false;
+        }
+        let size = self.size as isize;
+        let (x, y) = ((i % self.size) as isize, (i / self.size) as isize);
+        let mut enemies = 0;
+        let mut off_board = 0;
+        for (dx, dy) in [(-1, -1), (1, -1), (-1, 1), (1, 1)] {
+            let (nx, ny) = (x + dx, y + dy);
+            if nx < 0 || ny < 0 || nx >= size || ny >= size {
+                off_board += 1;
+            } else if self.board[(ny * size + nx) as usize] == player.other() {
+                enemies += 1;
+            }
+        }
+        if off_board > 0 {
+            enemies == 0
+        } else {
+            enemies < 2
+        }
+    }
+
+    // A point on the first or second line with no stone within two points
+    fn low_and_alone(&self, i: usize) -> bool {
+        let size = self.size;
+        let (x, y) = (i % size, i / size);
+        if x.min(y).min(size - 1 - x).min(size - 1 - y) >= 2 {
+            return false;
+        }
+        let near = |v: usize| v.saturating_sub(2)..=(v + 2).min(size - 1);
+        !near(y).any(|ny| near(x).any(|nx| self.board[ny * size + nx] != Player::None))
+    }
+
+    // Reports whether the group at `i` has a liberty. When it has none, the
+    // whole group is left in `self.group`; otherwise the search stops early.
+    fn group_has_liberty(&mut self, i: usize) -> bool {
+        self.stamp += 1;
+        let color = self.board[i];
+        self.group.clear();
+        self.group.push(i);
+        self.mark[i] = self.stamp;
+        let mut next = 0;
+        while next < self.group.len() {
+            let stone = self.group[next];
+            next += 1;
+            for n in self.neighbors(stone) {
+                if self.board[n] == Player::None {
+                    return true;
+                } else if self.board[n] == color && self.mark[n] != self.stamp {
+                    self.mark[n] = self.stamp;
+                    self.group.push(n);
+                }
+            }
+        }
+        false
+    }
+
+    // Liberties of the group at `i`, counted no further than two, and the first one found
+    fn liberties(&mut self, i: usize) -> (usize, Option<usize>) {
+        self.stamp += 1;
+        let color = self.board[i];
+        self.group.clear();
+        self.group.push(i);
+        self.mark[i] = self.stamp;
+        let mut first = None;
+        let mut next = 0;
+        while next < self.group.len() {
+            let stone = self.group[next];
+            next += 1;
+            for n in self.neighbors(stone) {
+                if self.board[n] == Player::None {
+                    match first {
+                        None => first = Some(n),
+                        Some(liberty) if liberty != n => return (2, first),
+                        Some(_) => {}
+                    }
+                } else if self.board[n] == color && self.mark[n] != self.stamp {
+                    self.mark[n] = self.stamp;
+                    self.group.push(n);
+                }
+            }
+        }
+        (first.map_or(0, |_| 1), first)
+    }
+
+    // Empty points that are the last liberty of a group of `color`
+    fn atari_points(&mut self, color: Player) -> Vec<usize> {
+        let mut points = Vec::new();
+        for k in 0..self.empties.len() {
+            let e = self.empties[k];
+            if self.neighbors(e).any(|n| self.board[n] == color && self.liberties(n) == (1, Some(e))) {
+                points.push(e);
+            }
+        }
+        points
+    }
+
+    // Whether a stone of `player` at the empty point `i` would be left with
+    // fewer than two liberties, assuming it captures nothing
+    fn self_atari(&mut self, i: usize, player: Player) -> bool {
+        self.board[i] = player;
+        let (liberties, _) = self.liberties(i);
+        self.board[i] = Player::None;
+        liberties < 2
+    }
+
+    fn play(&mut self, i: usize) -> bool {
+        if self.board[i] != Player::None || self.ko == Some(i) {
+            return false;
+        }
+        let player = self.to_move;
+        let opponent = player.other();
+        let adjacent: Vec<usize> = self.neighbors(i).collect();
+
+        // Reject suicide before touching the list of empty points
+        self.board[i] = player;
+        let captures = adjacent
+            .iter()
+            .any(|&n| self.board[n] == opponent && !self.group_has_liberty(n));
+        if !captures && !self.group_has_liberty(i) {
+            self.board[i] = Player::None;
+            return false;
+        }
+        self.board[i] = Player::None;
+        self.set(i, player);
+
+        let mut captured = 0;
+        let mut last_captured = i;
+        for &n in &adjacent {
+            if self.board[n] == opponent && !self.group_has_liberty(n) {
+                captured += self.group.len();
+                last_captured = self.group[0];
+                for k in 0..self.group.len() {
+                    let stone = self.group[k];
+                    self.set(stone, Player::None);
+                }
+            }
+        }
+
+        // A lone stone that took exactly one stone can be retaken at once
+        let lone = adjacent.iter().all(|&n| self.board[n] != player);
+        let liberties = adjacent.iter().filter(|&&n| self.board[n] == Player::None).count();
+        self.ko = (captured == 1 && lone && liberties == 1).then_some(last_captured);
+        self.to_move = opponent;
+        true
+    }
+
+    // Plays random moves until both sides pass and returns the winner. In
+    // Atari Go the first capture ends the game instead.
+    fn run(&mut self, rng: &mut ThreadRng) -> Player {
+        let points = self.size * self.size;
+        let mut passes = 0;
+        let mut moves = 0;
+        while passes < 2 && moves < points * 3 {
+            let player = self.to_move;
+            let mut played = false;
+            if self.first_capture {
+                if let Some(&i) = self.atari_points(player.other()).first() {
+                    if self.play(i) {
+                        return player;
+                    }
+                }
+                // Nothing can be captured, so no move below takes a stone either
+                for i in self.atari_points(player) {
+                    if !self.self_atari(i, player) && self.play(i) {
+                        played = true;
+                        break;
+                    }
+                }
+                played = played || self.play_random(rng, true);
+            }
+            played = played || self.play_random(rng, false);
+            if played {
+                passes = 0;
+            } else {
+                passes += 1;
+                self.ko = None;
+                self.to_move = player.other();
+            }
+            moves += 1;
+        }
+        self.winner()
+    }
+
+    // Tries empty points in random order; rejected ones are moved to the end
+    fn play_random(&mut self, rng: &mut ThreadRng, avoid_atari: bool) -> bool {
+        let player = self.to_move;
+        let mut remaining = self.empties.len();
+        while remaining > 0 {
+            let k = rng.gen_range(0..remaining);
+            let i = self.empties[k];
+            let rejected = self.is_eye(i, player) || (avoid_atari && self.self_atari(i, player));
+            if !rejected && self.play(i) {
+                return true;
+            }
+            remaining -= 1;
+            self.empties.swap(k, remaining);
+            self.empty_index[self.empties[k]] = k;
+            self.empty_index[self.empties[remaining]] = remaining;
+        }
+        false
+    }
+
+    // Area score: stones plus empty regions that touch only one colour
+    fn winner(&mut self) -> Player {
+        let mut black = 0.0;
+        let mut white = self.komi;
+        self.stamp += 1;
+        for i in 0..self.board.len() {
+            match self.board[i] {
+                Player::Black => black += 1.0,
+                Player::White => white += 1.0,
+                Player::None if self.mark[i] != self.stamp => {
+                    let mut region = vec![i];
+                    let mut borders = (false, false);
+                    self.mark[i] = self.stamp;
+                    let mut next = 0;
+                    while next < region.len() {
+                        let point = region[next];
+                        next += 1;
+                        for n in self.neighbors(point) {
+                            match self.board[n] {
+                                Player::Black => borders.0 = true,
+                                Player::White => borders.1 = true,
+                                Player::None if self.mark[n] != self.stamp => {
+                                    self.mark[n] = self.stamp;
+                                    region.push(n);
+                                }
+                                Player::None => {}
+                            }
+                        }
+                    }
+                    match borders {
+                        (true, false) => black += region.len() as f32,
+                        (false, true) => white += region.len() as f32,
+                        _ => {}
+                    }
+                }
+                Player::None => {}
+            }
+        }
+        if black > white {
+            Player::Black
+        } else {
+            Player::White
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::Rules;
+
+    fn line(mv: Move, size: usize) -> usize {
+        match mv {
+            Move::Place(x, y) => x.min(y).min(size - 1 - x).min(size - 1 - y),
+            Move::Pass => panic!("unexpected pass"),
+        }
+    }
+
+    #[test]
+    fn opening_stays_off_the_low_lines_on_big_boards() {
+        let mut game = Game::new(19, GameType::Go(Rules::Chinese));
+        let moves = candidate_moves(&game);
+        assert_eq!(moves.len(), 15 * 15);
+        assert!(moves.iter().all(|&mv| line(mv, 19) >= 2));
+
+        // Next to a stone the low points come back
+        game.play(3, 2).unwrap();
+        let moves = candidate_moves(&game);
+        assert!(moves.contains(&Move::Place(2, 1)) && moves.contains(&Move::Place(5, 0)));
+        assert!(!moves.contains(&Move::Place(6, 0)) && !moves.contains(&Move::Place(0, 10)));
+
+        // Small boards, and later in the game, every point is a candidate
+        assert_eq!(candidate_moves(&Game::new(9, GameType::Go(Rules::Chinese))).len(), 81);
+        for i in 0..38 {
+            game.play(4 + i % 12, 4 + i / 12).unwrap();
+        }
+        assert!(candidate_moves(&game).contains(&Move::Place(0, 18)));
+    }
+}
@@ -0,0 +1,193 @@
+use std::fmt;
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// Framing for the two-player network protocol. Each message is a 4-byte
+// big-endian length followed by that many bytes of JSON, so messages that TCP
+// splits up or runs together arrive whole and one at a time.
+
+// Far more than any message needs; a longer frame means the peer is not speaking the protocol
+pub const MAX_FRAME_LEN: usize = 64 * 1024;
+
+const HEADER_LEN: usize = 4;
+
+#[derive(Debug)]
+pub enum FrameError {
+    Io(io::Error),
+    TooLarge(usize),
+    // The connection closed partway through a frame
+    Truncated,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::Io(e) => write!(f, "Network error: {}", e),
+            FrameError::TooLarge(len) => {
+                write!(f, "Network message of {} bytes is over the limit of {}", len, MAX_FRAME_LEN)
+            }
+            FrameError::Truncated => write!(f, "Connection closed in the middle of a message"),
+        }
+    }
+}
+
+impl From<FrameError> for String {
+    fn from(e: FrameError) -> Self {
+        e.to_string()
+    }
+}
+
+impl From<io::Error> for FrameError {
+    fn from(e: io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
+
+pub struct FrameReader<R> {
+    reader: R,
+    // Bytes received but not yet handed out as a frame
+    buf: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+    pub fn new(reader: R) -> Self {
+        FrameReader { reader, buf: Vec::new() }
+    }
+
+    // The next frame's payload, or None once the peer has closed the
+    // connection between frames. Partial frames stay buffered when the future
+    // is dropped, so this can be raced against other work in `select!`.
+    pub async fn next_frame(&mut self) -> Result<Option<Vec<u8>>, FrameError> {
+        let mut chunk = [0; 4096];
+        loop {
+            if let Some(frame) = self.take_frame()? {
+                return Ok(Some(frame));
+            }
+            let n = self.reader.read(&mut chunk).await?;
+            if n == 0 {
+                return if self.buf.is_empty() { Ok(None) } else { Err(FrameError::Truncated) };
+            }
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    fn take_frame(&mut self) -> Result<Option<Vec<u8>>, FrameError> {
+        let Some(header) = self.buf.get(..HEADER_LEN) else {
+            return Ok(None);
+        };
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(FrameError::TooLarge(len));
+        }
+        if self.buf.len() < HEADER_LEN + len {
+            return Ok(None);
+        }
+        let frame = self.buf[HEADER_LEN..HEADER_LEN + len].to_vec();
+        self.buf.drain(..HEADER_LEN + len);
+        Ok(Some(frame))
+    }
+}
+
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, payload: &[u8]) -> Result<(), FrameError> {
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(FrameError::TooLarge(payload.len()));
+    }
+    writer.write_all(&(payload.len() as u32).to_be_bytes()).await?;
+    writer.write_all(payload).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use tokio::io::ReadBuf;
+
+    use super::*;
+
+    // Hands out one chunk per read, the way TCP may split or join frames
+    struct Chunks(VecDeque<Vec<u8>>);
+
+    impl AsyncRead for Chunks {
+        fn poll_read(mut self: Pin<&mut Self>, _: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+            if let Some(chunk) = self.0.pop_front() {
+                buf.put_slice(&chunk);
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn reader(chunks: &[&[u8]]) -> FrameReader<Chunks> {
+        FrameReader::new(Chunks(chunks.iter().map(|chunk| chunk.to_vec()).collect()))
+    }
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    #[tokio::test]
+    async fn frame_split_across_reads() {
+        let bytes = frame(b"{\"move\":[3,4]}");
+        let mut frames = reader(&[&bytes[..2], &bytes[2..7], &bytes[7..]]);
+        assert_eq!(frames.next_frame().await.unwrap().unwrap(), b"{\"move\":[3,4]}");
+        assert!(frames.next_frame().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn several_frames_in_one_read() {
+        let mut bytes = [frame(b"one"), frame(b""), frame(b"three")].concat();
+        bytes.extend_from_slice(&frame(b"four")[..3]);
+        let mut frames = reader(&[&bytes, &frame(b"four")[3..]]);
+        for expected in [&b"one"[..], b"", b"three", b"four"] {
+            assert_eq!(frames.next_frame().await.unwrap().unwrap(), expected);
+        }
+        assert!(frames.next_frame().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn frame_over_the_limit() {
+        let header = ((MAX_FRAME_LEN + 1) as u32).to_be_bytes();
+        let mut frames = reader(&[&header]);
+        assert!(matches!(frames.next_frame().await, Err(FrameError::TooLarge(len)) if len == MAX_FRAME_LEN + 1));
+
+        let mut sink = Vec::new();
+        let payload = vec![0; MAX_FRAME_LEN + 1];
+        assert!(matches!(write_frame(&mut sink, &payload).await, Err(FrameError::TooLarge(_))));
+        assert!(sink.is_empty());
+    }
+
+    #[tokio::test]
+    async fn eof_in_the_middle_of_a_frame() {
+        let bytes = frame(b"cut short");
+        let mut frames = reader(&[&bytes[..6]]);
+        assert!(matches!(frames.next_frame().await, Err(FrameError::Truncated)));
+        // A header cut short counts too
+        let mut frames = reader(&[&bytes[..2]]);
+        assert!(matches!(frames.next_frame().await, Err(FrameError::Truncated)));
+    }
+
+    #[tokio::test]
+    async fn write_frame_round_trip() {
+        let (mut client, server) = tokio::io::duplex(16);
+        let largest = vec![7; MAX_FRAME_LEN];
+        let payloads = [b"hello".to_vec(), Vec::new(), largest];
+        let sent = payloads.clone();
+        let writer = tokio::spawn(async move {
+            for payload in &sent {
+                write_frame(&mut client, payload).await.unwrap();
+            }
+        });
+        let mut frames = FrameReader::new(server);
+        for payload in &payloads {
+            assert_eq!(&frames.next_frame().await.unwrap().unwrap(), payload);
+        }
+        writer.await.unwrap();
+        assert!(frames.next_frame().await.unwrap().is_none());
+    }
+}
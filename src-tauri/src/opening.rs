@@ -0,0 +1,345 @@
+use serde::{Deserialize, Serialize};
+
+use crate::game::Player;
+
+// Opening protocols that take away the first player's advantage in Gomoku and
+// Renju: one player proposes a position and the other picks a colour
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum OpeningRule {
+    // The opener places two black stones and a white one, the other player picks a colour
+    Swap,
+    // As Swap, but the other player may instead add a white and a black stone
+    // and leave the choice of colour to the opener
+    Swap2,
+    // Three stones around the centre, a swap, the fourth move, another swap,
+    // then White picks one of 1-8 fifth moves that Black offers
+    Soosorv8,
+    // Moves one to four in growing squares around the centre, each followed
+    // by a swap, then White picks one of ten fifth moves that Black offers
+    Taraguchi10,
+}
+
+// The two players while colours are still open: the one who opens and the other
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Seat {
+    First,
+    Second,
+}
+
+impl Seat {
+    pub fn other(&self) -> Seat {
+        match self {
+            Seat::First => Seat::Second,
+            Seat::Second => Seat::First,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum OpeningStep {
+    // The next stone, in the usual colour order
+    Place,
+    // A choice of colour, or in Swap2 of placing two more stones
+    Choose,
+    // Soosõrv: White says how many fifth moves Black has to offer
+    DeclareOffers,
+    // Black proposes fifth moves, one point at a time
+    Offer,
+    // White picks one of the offered points as Black's fifth move
+    Pick,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum OpeningChoice {
+    // The player choosing takes Black or White
+    Black,
+    White,
+    // Swap2: place a white and a black stone and let the opener choose
+    PlaceTwo,
+    // Soosõrv: how many fifth moves Black has to offer
+    Offers(usize),
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Opening {
+    pub rule: OpeningRule,
+    // None once colours are settled and the game goes on normally
+    pub step: Option<OpeningStep>,
+    // Who has to place, choose or offer next
+    pub actor: Seat,
+    // Who plays Black
+    pub black: Seat,
+    pub offers: Vec<(usize, usize)>,
+    pub offer_count: usize,
+    // Moves played when the opening was over, which can no longer be taken back
+    pub moves: usize,
+    // Swap2: the second player chose to place two more stones
+    pub two_more: bool,
+}
+
+impl Opening {
+    pub fn new(rule: OpeningRule) -> Self {
+        Opening {
+            rule,
+            step: Some(OpeningStep::Place),
+            actor: Seat::First,
+            black: Seat::First,
+            offers: Vec::new(),
+            offer_count: 0,
+            moves: 0,
+            two_more: false,
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.step.is_none()
+    }
+
+    pub fn seat_of(&self, player: Player) -> Seat {
+        if player == Player::Black {
+            self.black
+        } else {
+            self.black.other()
+        }
+    }
+
+    // How far from the centre, counting in both directions, the next stone may
+    // go when `stones` are on the board
+    pub fn max_distance(&self, stones: usize) -> Option<usize> {
+        match self.rule {
+            OpeningRule::Soosorv8 if stones < 3 => Some(stones),
+            OpeningRule::Taraguchi10 if stones < 4 => Some(stones),
+            _ => None,
+        }
+    }
+
+    // Choices open to the actor at a Choose or DeclareOffers step
+    pub fn choices(&self, stones: usize) -> Vec<OpeningChoice> {
+        match self.step {
+            Some(OpeningStep::Choose) => {
+                let mut choices = vec![OpeningChoice::Black, OpeningChoice::White];
+                if self.rule == OpeningRule::Swap2 && stones == 3 {
+                    choices.push(OpeningChoice::PlaceTwo);
+                }
+                choices
+            }
+            Some(OpeningStep::DeclareOffers) => (1..=8).map(OpeningChoice::Offers).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // Moves on after a stone is placed; `stones` counts it
+    pub fn stone_placed(&mut self, stones: usize) {
+        let placer = self.seat_of(if stones % 2 == 1 { Player::Black } else { Player::White });
+        match self.rule {
+            OpeningRule::Swap | OpeningRule::Swap2 => {
+                if stones == 3 && !self.two_more {
+                    self.ask(OpeningStep::Choose, Seat::Second);
+                } else if stones == 5 {
+                    self.ask(OpeningStep::Choose, Seat::First);
+                }
+            }
+            OpeningRule::Soosorv8 => match stones {
+                3 => self.ask(OpeningStep::Choose, Seat::Second),
+                4 => self.ask(OpeningStep::DeclareOffers, placer),
+                5 => self.finish(stones),
+                _ => {}
+            },
+            OpeningRule::Taraguchi10 => match stones {
+                1..=4 => self.ask(OpeningStep::Choose, placer.other()),
+                _ => self.finish(stones),
+            },
+        }
+    }
+
+    // Applies a choice the actor is allowed to make
+    pub fn choose(&mut self, choice: OpeningChoice, stones: usize) {
+        let chooser = self.actor;
+        match choice {
+            OpeningChoice::Black => self.black = chooser,
+            OpeningChoice::White => self.black = chooser.other(),
+            OpeningChoice::PlaceTwo => {
+                self.two_more = true;
+                self.ask(OpeningStep::Place, chooser);
+                return;
+            }
+            OpeningChoice::Offers(count) => {
+                self.offer_count = count;
+                // Black may still swap before offering
+                self.ask(OpeningStep::Choose, chooser.other());
+                return;
+            }
+        }
+
+        match self.rule {
+            OpeningRule::Swap | OpeningRule::Swap2 => self.finish(stones),
+            OpeningRule::Soosorv8 if stones == 3 => self.ask(OpeningStep::Place, self.black.other()),
+            OpeningRule::Soosorv8 => self.ask(OpeningStep::Offer, self.black),
+            OpeningRule::Taraguchi10 if stones < 4 => {
+                let next = if stones % 2 == 1 { Player::White } else { Player::Black };
+                self.ask(OpeningStep::Place, self.seat_of(next));
+            }
+            OpeningRule::Taraguchi10 => {
+                self.offer_count = 10;
+                self.ask(OpeningStep::Offer, self.black);
+            }
+        }
+    }
+
+    // Records an offered fifth move; White picks once all are in
+    pub fn offer(&mut self, point: (usize, usize)) {
+        self.offers.push(point);
+        if self.offers.len() == self.offer_count {
+            self.ask(OpeningStep::Pick, self.black.other());
+        }
+    }
+
+    fn ask(&mut self, step: OpeningStep, actor: Seat) {
+        self.step = Some(step);
+        self.actor = actor;
+    }
+
+    fn finish(&mut self, stones: usize) {
+        self.step = None;
+        self.offers.clear();
+        self.moves = stones;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::Difficulty;
+    use crate::game::{Game, GameError, GameSettings, GameType};
+    use crate::rules::GomokuRule;
+
+    fn game(rule: OpeningRule) -> Game {
+        Game::with_settings(&GameSettings {
+            size: 15,
+            game_type: GameType::Gomoku(GomokuRule::Standard),
+            komi: None,
+            handicap: None,
+            difficulty: Difficulty::default(),
+            opening: Some(rule),
+        })
+        .unwrap()
+    }
+
+    fn step(game: &Game) -> Option<OpeningStep> {
+        game.opening.as_ref().unwrap().step
+    }
+
+    fn place(game: &mut Game, points: &[(usize, usize)]) {
+        for &(x, y) in points {
+            game.play(x, y).unwrap();
+        }
+    }
+
+    #[test]
+    fn swap_lets_the_second_player_pick_a_colour() {
+        let mut game = game(OpeningRule::Swap);
+        place(&mut game, &[(7, 7), (8, 8), (6, 8)]);
+        assert_eq!(step(&game), Some(OpeningStep::Choose));
+        assert_eq!(game.seat_to_act(), Seat::Second);
+        assert_eq!(game.play(9, 9), Err(GameError::OpeningChoiceExpected));
+        assert_eq!(game.choose_opening(OpeningChoice::PlaceTwo), Err(GameError::UnavailableChoice));
+        assert_eq!(game.choose_opening(OpeningChoice::Offers(2)), Err(GameError::UnavailableChoice));
+
+        game.choose_opening(OpeningChoice::White).unwrap();
+        let opening = game.opening.as_ref().unwrap();
+        assert!(opening.is_over());
+        assert_eq!(opening.black, Seat::First);
+        assert_eq!(game.seat_to_act(), Seat::Second);
+        assert_eq!(game.choose_opening(OpeningChoice::Black), Err(GameError::UnavailableChoice));
+    }
+
+    #[test]
+    fn swap2_can_hand_the_choice_back_after_two_more_stones() {
+        let mut game = game(OpeningRule::Swap2);
+        place(&mut game, &[(7, 7), (8, 8), (6, 8)]);
+        game.choose_opening(OpeningChoice::PlaceTwo).unwrap();
+        assert_eq!(step(&game), Some(OpeningStep::Place));
+        assert_eq!(game.seat_to_act(), Seat::Second);
+
+        place(&mut game, &[(6, 6), (8, 6)]);
+        assert_eq!(step(&game), Some(OpeningStep::Choose));
+        assert_eq!(game.seat_to_act(), Seat::First);
+        // Only the second player gets to place two more
+        assert_eq!(game.choose_opening(OpeningChoice::PlaceTwo), Err(GameError::UnavailableChoice));
+
+        game.choose_opening(OpeningChoice::Black).unwrap();
+        assert!(game.opening.as_ref().unwrap().is_over());
+        // White's sixth move falls to the second player
+        assert_eq!(game.seat_to_act(), Seat::Second);
+    }
+
+    #[test]
+    fn soosorv8_steps_through_both_swaps_and_the_offers() {
+        let mut game = game(OpeningRule::Soosorv8);
+        assert_eq!(game.play(8, 8), Err(GameError::OutsideOpeningArea));
+        place(&mut game, &[(7, 7), (8, 8)]);
+        assert_eq!(game.play(10, 7), Err(GameError::OutsideOpeningArea));
+        place(&mut game, &[(9, 7)]);
+
+        // The second player takes Black and White plays the fourth move
+        game.choose_opening(OpeningChoice::Black).unwrap();
+        assert_eq!(game.seat_to_act(), Seat::First);
+        place(&mut game, &[(10, 10)]);
+
+        assert_eq!(step(&game), Some(OpeningStep::DeclareOffers));
+        assert_eq!(game.choose_opening(OpeningChoice::Black), Err(GameError::UnavailableChoice));
+        assert_eq!(game.choose_opening(OpeningChoice::Offers(9)), Err(GameError::UnavailableChoice));
+        game.choose_opening(OpeningChoice::Offers(2)).unwrap();
+
+        // Black may swap once more before offering; here they stay Black
+        assert_eq!(step(&game), Some(OpeningStep::Choose));
+        assert_eq!(game.seat_to_act(), Seat::Second);
+        game.choose_opening(OpeningChoice::Black).unwrap();
+
+        assert_eq!(step(&game), Some(OpeningStep::Offer));
+        place(&mut game, &[(3, 3)]);
+        assert_eq!(game.play(3, 3), Err(GameError::Occupied));
+        place(&mut game, &[(11, 3)]);
+
+        assert_eq!(step(&game), Some(OpeningStep::Pick));
+        assert_eq!(game.seat_to_act(), Seat::First);
+        assert_eq!(game.play(3, 11), Err(GameError::NotOffered));
+        place(&mut game, &[(11, 3)]);
+        assert_eq!(game.board[3][11], Player::Black);
+        assert_eq!(game.board[3][3], Player::None);
+        assert!(game.opening.as_ref().unwrap().is_over());
+        assert_eq!(game.seat_to_act(), Seat::First);
+    }
+
+    #[test]
+    fn taraguchi10_swaps_after_each_of_the_first_four_moves() {
+        let mut game = game(OpeningRule::Taraguchi10);
+        place(&mut game, &[(7, 7)]);
+        game.choose_opening(OpeningChoice::White).unwrap();
+        assert_eq!(game.seat_to_act(), Seat::Second);
+        assert_eq!(game.play(9, 9), Err(GameError::OutsideOpeningArea));
+        place(&mut game, &[(8, 8)]);
+
+        game.choose_opening(OpeningChoice::Black).unwrap();
+        assert_eq!(game.opening.as_ref().unwrap().black, Seat::First);
+        place(&mut game, &[(9, 5)]);
+        game.choose_opening(OpeningChoice::White).unwrap();
+        place(&mut game, &[(4, 10)]);
+        assert_eq!(game.choose_opening(OpeningChoice::Offers(10)), Err(GameError::UnavailableChoice));
+        game.choose_opening(OpeningChoice::Black).unwrap();
+
+        // Black offers ten fifth moves for White to pick from
+        let black = game.opening.as_ref().unwrap().black;
+        assert_eq!(step(&game), Some(OpeningStep::Offer));
+        assert_eq!(game.seat_to_act(), black);
+        for x in 0..10 {
+            place(&mut game, &[(x, 0)]);
+        }
+        assert_eq!(step(&game), Some(OpeningStep::Pick));
+        assert_eq!(game.seat_to_act(), black.other());
+        assert_eq!(game.play(10, 0), Err(GameError::NotOffered));
+        place(&mut game, &[(4, 0)]);
+        assert!(game.opening.as_ref().unwrap().is_over());
+        assert_eq!(game.moves.len(), 5);
+    }
+}
@@ -0,0 +1,536 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::AtomicBool;
+
+use crate::ai::Difficulty;
+use crate::game::{Game, GameError, GameSettings, GameType, Move, Player};
+use crate::gomoku_ai;
+use crate::rules::GomokuRule;
+
+// Board sizes a brain accepts; anything larger could not be saved as SGF
+const SIZES: std::ops::RangeInclusive<usize> = 5..=26;
+
+// Time kept back from the manager's per-move limit for reading and answering
+const TIME_MARGIN_MS: u64 = 100;
+// Least time a move gets, for "as fast as possible" and for when the clock runs low
+const MIN_MOVE_MS: u64 = 20;
+// Rough number of moves still to come, to share out the time left in the match
+const MOVES_LEFT: u64 = 20;
+
+// Bits of INFO rule; with none of them five or more in a row wins
+const RULE_EXACT_FIVE: u32 = 1;
+const RULE_RENJU: u32 = 4;
+const RULE_CARO: u32 = 8;
+
+#[derive(Debug)]
+pub enum PiskvorkError {
+    Io(io::Error),
+    // The brain answered with "ERROR message" or "UNKNOWN message"
+    Brain(String),
+    // The brain said something that is not part of the protocol
+    Protocol(String),
+    Closed,
+}
+
+impl fmt::Display for PiskvorkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PiskvorkError::Io(e) => write!(f, "Gomoku engine I/O error: {}", e),
+            PiskvorkError::Brain(message) => write!(f, "Gomoku engine error: {}", message),
+            PiskvorkError::Protocol(message) => write!(f, "Gomoku protocol error: {}", message),
+            PiskvorkError::Closed => write!(f, "Gomoku engine exited"),
+        }
+    }
+}
+
+impl From<PiskvorkError> for String {
+    fn from(e: PiskvorkError) -> Self {
+        e.to_string()
+    }
+}
+
+impl From<io::Error> for PiskvorkError {
+    fn from(e: io::Error) -> Self {
+        PiskvorkError::Io(e)
+    }
+}
+
+// "x,y" with zero-based coordinates from the top left corner
+fn parse_point(text: &str, size: usize) -> Option<(usize, usize)> {
+    let (x, y) = text.trim().split_once(',')?;
+    let (x, y): (usize, usize) = (x.trim().parse().ok()?, y.trim().parse().ok()?);
+    (x < size && y < size).then_some((x, y))
+}
+
+// An external Gomoku AI following the Gomocup (Piskvork) protocol
+pub struct PiskvorkEngine {
+    // None when the brain is not a child process of ours
+    child: Option<Child>,
+    stdin: Box<dyn Write + Send>,
+    stdout: Box<dyn BufRead + Send>,
+    pub name: String,
+    // Board size the brain was last started with
+    size: Option<usize>,
+}
+
+impl PiskvorkEngine {
+    pub fn spawn(program: &str, args: &[String]) -> Result<Self, PiskvorkError> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().ok_or(PiskvorkError::Closed)?;
+        let stdout = BufReader::new(child.stdout.take().ok_or(PiskvorkError::Closed)?);
+        PiskvorkEngine::start(Some(child), Box::new(stdin), Box::new(stdout), program)
+    }
+
+    // Asks the brain for its name, falling back on the program's
+    fn start(
+        child: Option<Child>,
+        stdin: Box<dyn Write + Send>,
+        stdout: Box<dyn BufRead + Send>,
+        program: &str,
+    ) -> Result<Self, PiskvorkError> {
+        let mut engine = PiskvorkEngine {
+            child,
+            stdin,
+            stdout,
+            name: String::new(),
+            size: None,
+        };
+
+        // name="...", version="...", author="..." and so on
+        let about = engine.request(&["ABOUT"])?;
+        let field = |key: &str| {
+            about
+                .split(',')
+                .filter_map(|part| part.split_once('='))
+                .find(|(k, _)| k.trim() == key)
+                .map(|(_, v)| v.trim().trim_matches('"').to_string())
+        };
+        engine.name = match (field("name"), field("version")) {
+            (Some(name), Some(version)) => format!("{} {}", name, version),
+            (Some(name), None) => name,
+            _ => program.to_string(),
+        };
+        Ok(engine)
+    }
+
+    fn send(&mut self, lines: &[&str]) -> Result<(), PiskvorkError> {
+        for line in lines {
+            writeln!(self.stdin, "{}", line)?;
+        }
+        self.stdin.flush()?;
+        Ok(())
+    }
+
+    // Sends the lines and returns the brain's answer, skipping its chatter
+    fn request(&mut self, lines: &[&str]) -> Result<String, PiskvorkError> {
+        self.send(lines)?;
+        loop {
+            let mut line = String::new();
+            if self.stdout.read_line(&mut line)? == 0 {
+                return Err(PiskvorkError::Closed);
+            }
+            let line = line.trim();
+            let (word, rest) = line.split_once(' ').unwrap_or((line, ""));
+            match word {
+                "" | "MESSAGE" | "DEBUG" | "SUGGEST" => continue,
+                "ERROR" | "UNKNOWN" => return Err(PiskvorkError::Brain(rest.to_string())),
+                _ => return Ok(line.to_string()),
+            }
+        }
+    }
+
+    // Asks the brain to move for the player to move, sending it the whole board
+    pub fn genmove(&mut self, game: &Game) -> Result<(usize, usize), PiskvorkError> {
+        let size = game.size;
+        if self.size != Some(size) {
+            let reply = self.request(&[&format!("START {}", size)])?;
+            if reply != "OK" {
+                return Err(PiskvorkError::Protocol(format!("unexpected reply to START \"{}\"", reply)));
+            }
+            self.size = Some(size);
+        }
+        // The brain gets as long as the built-in engine would at this difficulty
+        if let Some(ms) = game.difficulty.strength().gomoku.time_limit_ms {
+            self.send(&[&format!("INFO timeout_turn {}", ms)])?;
+        }
+        let rule = match game.game_type {
+            GameType::Gomoku(GomokuRule::Standard) => RULE_EXACT_FIVE,
+            GameType::Gomoku(GomokuRule::Caro) => RULE_CARO,
+            GameType::Renju => RULE_RENJU,
+            _ => 0,
+        };
+        self.send(&[&format!("INFO rule {}", rule)])?;
+
+        // Stones in the order they were played; 1 marks the brain's own
+        let me = game.current_turn;
+        let stones = game
+            .setup_stones
+            .iter()
+            .copied()
+            .chain(game.moves.iter().filter_map(|record| match record.mv {
+                Move::Place(x, y) => Some(((x, y), record.player)),
+                Move::Pass => None,
+            }))
+            .filter(|&((x, y), _)| game.board[y][x] != Player::None);
+        let mut lines = vec!["BOARD".to_string()];
+        lines.extend(stones.map(|((x, y), player)| format!("{},{},{}", x, y, if player == me { 1 } else { 2 })));
+        lines.push("DONE".to_string());
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+
+        let reply = self.request(&lines)?;
+        parse_point(&reply, size).ok_or_else(|| PiskvorkError::Protocol(format!("bad move \"{}\"", reply)))
+    }
+}
+
+impl Drop for PiskvorkEngine {
+    fn drop(&mut self) {
+        let _ = self.send(&["END"]);
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+// The built-in Gomoku engine behind the Gomocup protocol, for Piskvork and
+// other tournament managers
+pub struct PiskvorkBrain {
+    difficulty: Difficulty,
+    game: Option<Game>,
+    // Gomoku rule or Renju, from INFO rule
+    game_type: GameType,
+    // Per-move time limit from the manager; 0 means as fast as possible
+    timeout_turn: Option<u64>,
+    // Time for the whole match, or None when it is unlimited, and what is left of it
+    timeout_match: Option<u64>,
+    time_left: Option<u64>,
+}
+
+impl PiskvorkBrain {
+    pub fn new(difficulty: Difficulty) -> Self {
+        PiskvorkBrain {
+            difficulty,
+            game: None,
+            game_type: GameType::Gomoku(GomokuRule::Freestyle),
+            timeout_turn: None,
+            timeout_match: None,
+            time_left: None,
+        }
+    }
+
+    // Answers commands until END or the end of the input
+    pub fn run(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        let mut lines = input.lines();
+        while let Some(line) = lines.next() {
+            let line = line?;
+            let line = line.trim();
+            let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+            let command = command.to_ascii_uppercase();
+            let reply = match command.as_str() {
+                "" => continue,
+                "END" => break,
+                "BOARD" => {
+                    // Stones follow one per line until DONE
+                    let mut stones = Vec::new();
+                    for line in lines.by_ref() {
+                        let line = line?;
+                        if line.trim().eq_ignore_ascii_case("DONE") {
+                            break;
+                        }
+                        stones.push(line);
+                    }
+                    self.board(&stones)
+                }
+                _ => self.handle(&command, args.trim()),
+            };
+            match reply {
+                Ok(Some(text)) => writeln!(output, "{}", text)?,
+                Ok(None) => {}
+                Err(message) => writeln!(output, "ERROR {}", message)?,
+            }
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, command: &str, args: &str) -> Result<Option<String>, String> {
+        match command {
+            "START" => {
+                let size: usize = args.parse().map_err(|_| "bad board size".to_string())?;
+                if !SIZES.contains(&size) {
+                    return Err(format!("unsupported board size {}", size));
+                }
+                self.game = Some(self.new_game(size)?);
+                Ok(Some("OK".to_string()))
+            }
+            "RESTART" => {
+                let size = self.game()?.size;
+                self.game = Some(self.new_game(size)?);
+                Ok(Some("OK".to_string()))
+            }
+            "BEGIN" => self.reply().map(Some),
+            "TURN" => {
+                let game = self.game()?;
+                let (x, y) = parse_point(args, game.size).ok_or("bad coordinates")?;
+                game.play(x, y).map_err(|e| e.to_string())?;
+                self.reply().map(Some)
+            }
+            "TAKEBACK" => {
+                let game = self.game()?;
+                let point = parse_point(args, game.size).ok_or("bad coordinates")?;
+                // Either the last move, or a stone that came with BOARD
+                match game.moves.last().map(|record| record.mv) {
+                    Some(Move::Place(x, y)) if (x, y) == point => {
+                        game.undo().map_err(|e| e.to_string())?;
+                    }
+                    _ if game.setup_stones.iter().any(|&(p, _)| p == point) => {
+                        game.setup_stone(point.0, point.1, Player::None).map_err(|e| e.to_string())?;
+                        game.current_turn = game.current_turn.other();
+                    }
+                    _ => return Err("can only take back the last move".to_string()),
+                }
+                Ok(Some("OK".to_string()))
+            }
+            "INFO" => {
+                let (key, value) = args.split_once(' ').unwrap_or((args, ""));
+                // Other keys (memory, match time, ...) are accepted and ignored
+                match key {
+                    "timeout_turn" => self.timeout_turn = value.trim().parse().ok(),
+                    "timeout_match" => self.timeout_match = value.trim().parse().ok().filter(|&ms| ms > 0),
+                    "time_left" => self.time_left = value.trim().parse().ok(),
+                    "rule" => {
+                        let rule: u32 = value.trim().parse().unwrap_or(0);
+                        self.game_type = if rule & RULE_RENJU != 0 {
+                            GameType::Renju
+                        } else if rule & RULE_CARO != 0 {
+                            GameType::Gomoku(GomokuRule::Caro)
+                        } else if rule & RULE_EXACT_FIVE != 0 {
+                            GameType::Gomoku(GomokuRule::Standard)
+                        } else {
+                            GameType::Gomoku(GomokuRule::Freestyle)
+                        };
+                        if let Some(game) = self.game.as_mut() {
+                            game.game_type = self.game_type;
+                        }
+                    }
+                    _ => {}
+                }
+                Ok(None)
+            }
+            "ABOUT" => Ok(Some(format!(
+                "name=\"go-game\", version=\"{}\"",
+                env!("CARGO_PKG_VERSION")
+            ))),
+            _ => Ok(Some(format!("UNKNOWN {}", command))),
+        }
+    }
+
+    // Sets up the position sent with BOARD and answers with a move
+    fn board(&mut self, stones: &[String]) -> Result<Option<String>, String> {
+        let size = self.game()?.size;
+        let mut game = self.new_game(size)?;
+        let (mut own, mut theirs) = (Vec::new(), Vec::new());
+        for stone in stones {
+            let (point, field) = stone.trim().rsplit_once(',').ok_or("bad board line")?;
+            let point = parse_point(point, size).ok_or("bad coordinates")?;
+            match field.trim() {
+                "1" => own.push(point),
+                "2" => theirs.push(point),
+                // Winning line of a continuous game, which we do not play
+                "3" => {}
+                _ => return Err("bad board line".to_string()),
+            }
+        }
+        // Black moves first, so the brain is White when the opponent has more stones
+        let me = if theirs.len() > own.len() { Player::White } else { Player::Black };
+        for &(x, y) in &own {
+            game.setup_stone(x, y, me).map_err(|e| e.to_string())?;
+        }
+        for &(x, y) in &theirs {
+            game.setup_stone(x, y, me.other()).map_err(|e| e.to_string())?;
+        }
+        game.current_turn = me;
+        self.game = Some(game);
+        self.reply().map(Some)
+    }
+
+    fn new_game(&self, size: usize) -> Result<Game, String> {
+        let settings = GameSettings {
+            size,
+            game_type: self.game_type,
+            komi: None,
+            handicap: None,
+            difficulty: self.difficulty,
+            opening: None,
+        };
+        Game::with_settings(&settings).map_err(|e: GameError| e.to_string())
+    }
+
+    fn game(&mut self) -> Result<&mut Game, String> {
+        self.game.as_mut().ok_or_else(|| "START was not sent".to_string())
+    }
+
+    // The most one move may take: the per-move limit less a margin, and a
+    // share of what is left of the match
+    fn move_budget(&self) -> Option<u64> {
+        let turn = self.timeout_turn.map(|ms| ms.saturating_sub(TIME_MARGIN_MS));
+        let share = self.time_left.or(self.timeout_match).map(|ms| ms / MOVES_LEFT);
+        let budget = match (turn, share) {
+            (Some(turn), Some(share)) => Some(turn.min(share)),
+            (turn, share) => turn.or(share),
+        };
+        budget.map(|ms| ms.max(MIN_MOVE_MS))
+    }
+
+    // Picks and plays a move for the side to move
+    fn reply(&mut self) -> Result<String, String> {
+        let mut config = self.difficulty.strength().gomoku;
+        if let Some(limit) = self.move_budget() {
+            config.time_limit_ms = Some(config.time_limit_ms.map_or(limit, |ms| ms.min(limit)));
+        }
+        let game = self.game()?;
+        let stop = AtomicBool::new(false);
+        let (x, y) = gomoku_ai::best_move(game, &config, &stop, &mut |_| {}).ok_or("no move left")?;
+        game.play(x, y).map_err(|e| e.to_string())?;
+        Ok(format!("{},{}", x, y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_util::{script, transcript, Sent};
+
+    // A brain that answers with the scripted lines in order
+    fn fake_engine(lines: &str) -> Result<(PiskvorkEngine, Sent), PiskvorkError> {
+        let sent = Sent::default();
+        let engine = PiskvorkEngine::start(None, Box::new(sent.clone()), script(lines), "fake-brain")?;
+        Ok((engine, sent))
+    }
+
+    // Feeds the script to a brain and returns its answers, one per line
+    fn serve(script: &str) -> Vec<String> {
+        let mut brain = PiskvorkBrain::new(Difficulty::Hard);
+        let output = transcript(script, |input, output| brain.run(input, output));
+        output.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn engine_handshake_reads_about() {
+        let (engine, sent) = fake_engine("MESSAGE starting up\nname=\"Fake\", version=\"1.0\", author=\"Someone\"\n").unwrap();
+        assert_eq!(engine.name, "Fake 1.0");
+        assert_eq!(sent.take(), ["ABOUT"]);
+
+        let (engine, _) = fake_engine("author=\"Someone\"\n").unwrap();
+        assert_eq!(engine.name, "fake-brain");
+
+        assert!(matches!(fake_engine("UNKNOWN ABOUT\n"), Err(PiskvorkError::Brain(_))));
+        assert!(matches!(fake_engine(""), Err(PiskvorkError::Closed)));
+    }
+
+    #[test]
+    fn engine_genmove_sends_the_board() {
+        let (mut engine, sent) = fake_engine("name=\"Fake\"\nOK\nDEBUG thinking\n8,7\nERROR out of memory\n").unwrap();
+        sent.take();
+        let mut game = Game::with_settings(&GameSettings {
+            size: 15,
+            game_type: GameType::Gomoku(GomokuRule::Standard),
+            komi: None,
+            handicap: None,
+            difficulty: Difficulty::Medium,
+            opening: None,
+        })
+        .unwrap();
+        game.play(7, 7).unwrap();
+        game.play(3, 4).unwrap();
+        game.play(7, 8).unwrap();
+
+        assert_eq!(engine.genmove(&game).unwrap(), (8, 7));
+        assert_eq!(
+            sent.take(),
+            ["START 15", "INFO timeout_turn 2000", "INFO rule 1", "BOARD", "7,7,2", "3,4,1", "7,8,2", "DONE"]
+        );
+        // Already started at this size, so only the board is sent again
+        assert!(matches!(engine.genmove(&game), Err(PiskvorkError::Brain(message)) if message == "out of memory"));
+        assert_eq!(sent.take()[0], "INFO timeout_turn 2000");
+        assert!(matches!(engine.genmove(&game), Err(PiskvorkError::Closed)));
+    }
+
+    #[test]
+    fn brain_answers_commands() {
+        let answers = serve("ABOUT\nstart 15\nINFO timeout_turn 300\nINFO max_memory 0\nFOO bar\nSTART 99\nRESTART\nEND\nABOUT\n");
+        assert_eq!(
+            answers,
+            [
+                format!("name=\"go-game\", version=\"{}\"", env!("CARGO_PKG_VERSION")).as_str(),
+                "OK",
+                "UNKNOWN FOO",
+                "ERROR unsupported board size 99",
+                "OK",
+            ]
+        );
+        assert_eq!(serve("BEGIN\n"), ["ERROR START was not sent"]);
+    }
+
+    #[test]
+    fn brain_shares_out_its_time() {
+        let mut brain = PiskvorkBrain::new(Difficulty::Hard);
+        assert_eq!(brain.move_budget(), None);
+        let mut info = |line: &str| brain.run(Cursor::new(format!("INFO {}\n", line)), io::sink()).unwrap();
+        info("timeout_match 0");
+        info("timeout_turn 0");
+        assert_eq!(brain.move_budget(), Some(MIN_MOVE_MS));
+        brain.timeout_turn = Some(5_000);
+        assert_eq!(brain.move_budget(), Some(4_900));
+        brain.timeout_match = Some(60_000);
+        assert_eq!(brain.move_budget(), Some(3_000));
+        brain.time_left = Some(10_000);
+        assert_eq!(brain.move_budget(), Some(500));
+        brain.time_left = Some(0);
+        assert_eq!(brain.move_budget(), Some(MIN_MOVE_MS));
+
+        // As fast as possible really is fast, well under the difficulty's limit
+        let started = std::time::Instant::now();
+        let answers = serve("START 15\nINFO timeout_turn 0\nBOARD\n7,7,2\n8,8,1\n6,8,2\nDONE\n");
+        assert!(parse_point(&answers[1], 15).is_some());
+        assert!(started.elapsed() < std::time::Duration::from_millis(1_000));
+    }
+
+    #[test]
+    fn brain_plays_turns_and_takes_back() {
+        let answers = serve("START 15\nINFO timeout_turn 300\nBEGIN\nTURN 0,0\nTURN 0,0\nTURN 15,0\n");
+        assert_eq!(answers.len(), 5);
+        assert_eq!(answers[0], "OK");
+        let first = parse_point(&answers[1], 15).unwrap();
+        let second = parse_point(&answers[2], 15).unwrap();
+        assert!(first != (0, 0) && second != (0, 0) && first != second);
+        assert!(answers[3].starts_with("ERROR "));
+        assert_eq!(answers[4], "ERROR bad coordinates");
+
+        // The brain's reply has to be taken back before the move it answered
+        let script = |reply: &str| format!("START 15\nINFO timeout_turn 300\nTURN 0,0\nTAKEBACK 0,0\nTAKEBACK {}\nTAKEBACK 0,0\n", reply);
+        let answers = serve(&script("14,14"));
+        assert_eq!(answers[2], "ERROR can only take back the last move");
+        let answers = serve(&script(&answers[1]));
+        assert_eq!(&answers[2..], ["ERROR can only take back the last move", "OK", "OK"]);
+    }
+
+    #[test]
+    fn brain_completes_five_from_board() {
+        // Four in a column with the top end blocked: only 7,7 wins
+        let script = "START 15\nINFO timeout_turn 300\nINFO rule 1\nBOARD\n7,3,1\n7,4,1\n7,5,1\n7,6,1\n7,2,2\n0,0,2\n0,5,2\n14,14,2\nDONE\n";
+        assert_eq!(serve(script), ["OK", "7,7"]);
+
+        // A stone sent with BOARD can be taken back even though it was not the last move
+        let answers = serve("START 15\nINFO timeout_turn 300\nBOARD\n7,7,2\nDONE\nTAKEBACK 7,7\nTAKEBACK 7,7\n");
+        assert_eq!(&answers[2..], ["OK", "ERROR can only take back the last move"]);
+    }
+}
@@ -0,0 +1,222 @@
+use serde::{Deserialize, Serialize};
+
+use crate::game::Player;
+
+// Renju restrictions on Black, who moves first: a five still wins, but
+// otherwise these shapes may not be made
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Forbidden {
+    // Two or more open threes at once
+    DoubleThree,
+    // Two or more fours at once, on different lines or the same one
+    DoubleFour,
+    // Six or more in a row
+    Overline,
+}
+
+const DIRECTIONS: [(isize, isize); 4] = [(1, 0), (0, 1), (1, 1), (1, -1)];
+// How deep to follow a three whose four-making point might itself be forbidden
+const MAX_DEPTH: u32 = 4;
+
+// Whether a Black stone at the empty point `i` of a flat board is forbidden, and why
+pub fn forbidden_move(board: &[Player], size: usize, i: usize) -> Option<Forbidden> {
+    if board[i] != Player::None || !could_be_forbidden(board, size, i) {
+        return None;
+    }
+    let mut position = Position {
+        board: board.to_vec(),
+        size,
+    };
+    position.board[i] = Player::Black;
+    position.classify(i, 0)
+}
+
+// Cheap test that rules out most points: every forbidden shape needs Black
+// stones on two lines through the point, or at least four on one
+fn could_be_forbidden(board: &[Player], size: usize, i: usize) -> bool {
+    let (x, y) = ((i % size) as isize, (i / size) as isize);
+    let counts = DIRECTIONS.map(|(dx, dy)| {
+        (-5..=5)
+            .filter(|&k| k != 0)
+            .filter(|&k| {
+                let (nx, ny) = (x + dx * k, y + dy * k);
+                let inside = nx >= 0 && ny >= 0 && nx < size as isize && ny < size as isize;
+                inside && board[(ny * size as isize + nx) as usize] == Player::Black
+            })
+            .count()
+    });
+    counts.iter().filter(|&&n| n >= 2).count() >= 2 || counts.iter().any(|&n| n >= 4)
+}
+
+struct Position {
+    board: Vec<Player>,
+    size: usize,
+}
+
+impl Position {
+    fn offset(&self, i: usize, (dx, dy): (isize, isize), k: isize) -> Option<usize> {
+        let x = (i % self.size) as isize + dx * k;
+        let y = (i / self.size) as isize + dy * k;
+        let size = self.size as isize;
+        (x >= 0 && y >= 0 && x < size && y < size).then(|| (y * size + x) as usize)
+    }
+
+    // Black stones behind and ahead of `i` in an unbroken line
+    fn run(&self, i: usize, d: (isize, isize)) -> (isize, isize) {
+        let count = |sign: isize| {
+            (1..)
+                .take_while(|&k| self.offset(i, d, sign * k).is_some_and(|p| self.board[p] == Player::Black))
+                .count() as isize
+        };
+        (count(-1), count(1))
+    }
+
+    // Offsets along `d`, in order, of empty points where Black would make
+    // exactly five through `i`
+    fn five_points(&mut self, i: usize, d: (isize, isize)) -> Vec<isize> {
+        let mut points = Vec::new();
+        for k in (-4..=4).filter(|&k| k != 0) {
+            let Some(p) = self.offset(i, d, k).filter(|&p| self.board[p] == Player::None) else {
+                continue;
+            };
+            self.board[p] = Player::Black;
+            let (back, ahead) = self.run(i, d);
+            if back + ahead + 1 == 5 && (-back..=ahead).contains(&k) {
+                points.push(k);
+            }
+            self.board[p] = Player::None;
+        }
+        points
+    }
+
+    // Fours through `i` along `d`; an open four is one four, X.XXX.X is two
+    fn fours(&mut self, i: usize, d: (isize, isize)) -> usize {
+        match self.five_points(i, d)[..] {
+            [a, b] if b - a == 5 => 1,
+            ref points => points.len().min(2),
+        }
+    }
+
+    // A four with a five point at each end
+    fn is_straight_four(&mut self, i: usize, d: (isize, isize)) -> bool {
+        self.five_points(i, d).windows(2).any(|w| w[1] - w[0] == 5)
+    }
+
+    // Whether the stone at `i` makes a three along `d`: a shape that becomes a
+    // straight four at a point where Black is allowed to play
+    fn is_three(&mut self, i: usize, d: (isize, isize), depth: u32) -> bool {
+        for k in (-4..=4).filter(|&k| k != 0) {
+            let Some(p) = self.offset(i, d, k).filter(|&p| self.board[p] == Player::None) else {
+                continue;
+            };
+            self.board[p] = Player::Black;
+            let three = self.is_straight_four(i, d) && (depth >= MAX_DEPTH || self.classify(p, depth + 1).is_none());
+            self.board[p] = Player::None;
+            if three {
+                return true;
+            }
+        }
+        false
+    }
+
+    // Classifies the Black stone just placed at `i`
+    fn classify(&mut self, i: usize, depth: u32) -> Option<Forbidden> {
+        let mut overline = false;
+        for d in DIRECTIONS {
+            let (back, ahead) = self.run(i, d);
+            match back + ahead + 1 {
+                // Making five wins, even alongside a forbidden shape
+                5 => return None,
+                n if n > 5 => overline = true,
+                _ => {}
+            }
+        }
+        if overline {
+            return Some(Forbidden::Overline);
+        }
+
+        let fours = DIRECTIONS.map(|d| self.fours(i, d));
+        if fours.iter().sum::<usize>() >= 2 {
+            return Some(Forbidden::DoubleFour);
+        }
+        let mut threes = 0;
+        for (d, fours) in DIRECTIONS.into_iter().zip(fours) {
+            if fours == 0 && self.is_three(i, d, depth) {
+                threes += 1;
+            }
+        }
+        (threes >= 2).then_some(Forbidden::DoubleThree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Game, GameError, GameType};
+
+    const SIZE: usize = 15;
+    // The point every test plays at
+    const CENTER: (usize, usize) = (7, 7);
+
+    fn black_at(stones: &[(usize, usize)]) -> Option<Forbidden> {
+        let mut board = vec![Player::None; SIZE * SIZE];
+        for &(x, y) in stones {
+            board[y * SIZE + x] = Player::Black;
+        }
+        forbidden_move(&board, SIZE, CENTER.1 * SIZE + CENTER.0)
+    }
+
+    // Open threes across and down through the center
+    const DOUBLE_THREE: [(usize, usize); 4] = [(6, 7), (8, 7), (7, 8), (7, 9)];
+
+    #[test]
+    fn two_open_threes_are_forbidden() {
+        assert_eq!(black_at(&DOUBLE_THREE), Some(Forbidden::DoubleThree));
+        assert_eq!(black_at(&DOUBLE_THREE[..3]), None);
+    }
+
+    #[test]
+    fn a_three_whose_fours_are_forbidden_does_not_count() {
+        // Both points that would straighten the row into an open four also make
+        // a second four down their column, so the row is only a fake three
+        let mut stones = DOUBLE_THREE.to_vec();
+        stones.extend([(5, 8), (5, 9), (5, 10), (9, 8), (9, 9), (9, 10)]);
+        assert_eq!(black_at(&stones), None);
+    }
+
+    #[test]
+    fn two_fours_on_one_line_are_forbidden() {
+        // X.XXX.X: a five either side of the center
+        assert_eq!(black_at(&[(3, 7), (5, 7), (6, 7), (9, 7)]), Some(Forbidden::DoubleFour));
+        assert_eq!(black_at(&[(5, 7), (6, 7), (9, 7)]), None);
+    }
+
+    #[test]
+    fn six_in_a_row_is_forbidden() {
+        assert_eq!(black_at(&[(2, 7), (3, 7), (4, 7), (5, 7), (6, 7)]), Some(Forbidden::Overline));
+        assert_eq!(black_at(&[(3, 7), (4, 7), (5, 7), (6, 7), (8, 7), (9, 7)]), Some(Forbidden::Overline));
+    }
+
+    #[test]
+    fn five_wins_even_when_forbidden_shapes_come_with_it() {
+        let mut stones = DOUBLE_THREE.to_vec();
+        stones.extend([(8, 6), (9, 5), (10, 4), (11, 3)]);
+        assert_eq!(black_at(&stones), None);
+        // The same shapes without the five
+        stones.truncate(DOUBLE_THREE.len() + 3);
+        assert_eq!(black_at(&stones), Some(Forbidden::DoubleThree));
+    }
+
+    #[test]
+    fn white_is_free_to_make_any_shape() {
+        let mut game = Game::new(SIZE, GameType::Renju);
+        for &(x, y) in &DOUBLE_THREE {
+            game.setup_stone(x, y, Player::White).unwrap();
+            game.setup_stone(x + 4, y + 4, Player::Black).unwrap();
+        }
+        // Black to move may not make the same shape lower down
+        assert_eq!(game.play(11, 11), Err(GameError::Forbidden(Forbidden::DoubleThree)));
+        game.play(0, 0).unwrap();
+        game.play(CENTER.0, CENTER.1).unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum ScoringMethod {
+    // Chinese: stones on the board plus surrounded territory
+    Area,
+    // Japanese: surrounded territory plus prisoners
+    Territory,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum KoRule {
+    // Only the immediate recapture is forbidden
+    Simple,
+    // No move may recreate any earlier board position
+    PositionalSuperko,
+    // No move may recreate an earlier position with the same player to move
+    SituationalSuperko,
+}
+
+// Extra points White receives in handicap games
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum HandicapCompensation {
+    None,
+    // One point per handicap stone (Chinese)
+    PerStone,
+    // One point per handicap stone after the first (AGA)
+    PerStoneAfterFirst,
+}
+
+impl HandicapCompensation {
+    pub fn points(&self, handicap: usize) -> f32 {
+        match self {
+            HandicapCompensation::None => 0.0,
+            HandicapCompensation::PerStone => handicap as f32,
+            HandicapCompensation::PerStoneAfterFirst => handicap.saturating_sub(1) as f32,
+        }
+    }
+}
+
+// Named Go rule sets that can be agreed on before a game
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Rules {
+    Chinese,
+    Japanese,
+    Aga,
+    NewZealand,
+    TrompTaylor,
+}
+
+// What makes a line of stones a win in Gomoku
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum GomokuRule {
+    // Five or more in a row
+    Freestyle,
+    // Exactly five; six or more does not count (standard Gomoku, the Gomocup rule)
+    Standard,
+    // Five or more, unless the opponent has closed both ends
+    Caro,
+}
+
+impl GomokuRule {
+    pub fn from_name(name: &str) -> Option<GomokuRule> {
+        let name = name.trim().to_ascii_lowercase();
+        match name.as_str() {
+            n if n.starts_with("free") => Some(GomokuRule::Freestyle),
+            n if n.starts_with("standard") || n.starts_with("exact") => Some(GomokuRule::Standard),
+            "caro" => Some(GomokuRule::Caro),
+            _ => None,
+        }
+    }
+
+    // Whether an unbroken line of `length` stones wins, given whether the
+    // opponent has a stone just past each end
+    pub fn is_win(&self, length: usize, blocked: (bool, bool)) -> bool {
+        match self {
+            GomokuRule::Freestyle => length >= 5,
+            GomokuRule::Standard => length == 5,
+            GomokuRule::Caro => length >= 5 && !(blocked.0 && blocked.1),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct RuleSet {
+    pub suicide_allowed: bool,
+    pub ko_rule: KoRule,
+    pub scoring: ScoringMethod,
+    pub default_komi: f32,
+    pub handicap_compensation: HandicapCompensation,
+}
+
+impl Rules {
+    // Loose match on the usual ways of writing a rule set's name, as in SGF RU
+    pub fn from_name(name: &str) -> Option<Rules> {
+        let name = name.trim().to_ascii_lowercase();
+        match name.as_str() {
+            n if n.starts_with("chin") => Some(Rules::Chinese),
+            n if n.starts_with("jap") => Some(Rules::Japanese),
+            "aga" => Some(Rules::Aga),
+            n if n == "nz" || n.starts_with("new zealand") => Some(Rules::NewZealand),
+            n if n.starts_with("tromp") || n == "tt" => Some(Rules::TrompTaylor),
+            _ => None,
+        }
+    }
+
+    pub fn rule_set(&self) -> RuleSet {
+        match self {
+            Rules::Chinese => RuleSet {
+                suicide_allowed: false,
+                ko_rule: KoRule::PositionalSuperko,
+                scoring: ScoringMethod::Area,
+                default_komi: 7.5,
+                handicap_compensation: HandicapCompensation::PerStone,
+            },
+            Rules::Japanese => RuleSet {
+                suicide_allowed: false,
+                ko_rule: KoRule::Simple,
+                scoring: ScoringMethod::Territory,
+                default_komi: 6.5,
+                handicap_compensation: HandicapCompensation::None,
+            },
+            Rules::Aga => RuleSet {
+                suicide_allowed: false,
+                ko_rule: KoRule::SituationalSuperko,
+                scoring: ScoringMethod::Area,
+                default_komi: 7.5,
+                handicap_compensation: HandicapCompensation::PerStoneAfterFirst,
+            },
+            Rules::NewZealand => RuleSet {
+                suicide_allowed: true,
+                ko_rule: KoRule::SituationalSuperko,
+                scoring: ScoringMethod::Area,
+                default_komi: 7.0,
+                handicap_compensation: HandicapCompensation::None,
+            },
+            Rules::TrompTaylor => RuleSet {
+                suicide_allowed: true,
+                ko_rule: KoRule::PositionalSuperko,
+                scoring: ScoringMethod::Area,
+                default_komi: 7.5,
+                handicap_compensation: HandicapCompensation::None,
+            },
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use serde::{Deserialize, Serialize};
+
+use crate::game::{Game, GameError, GamePhase, Player};
+use crate::rules::ScoringMethod;
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct PlayerScore {
+    pub territory: usize,
+    pub stones: usize,
+    pub captures: usize,
+    pub komi: f32,
+    // Compensation for Black's handicap stones, if the rules give any
+    pub handicap: f32,
+    pub total: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Score {
+    pub method: ScoringMethod,
+    pub black: PlayerScore,
+    pub white: PlayerScore,
+    pub komi: f32,
+    pub winner: Option<Player>,
+    // e.g. "B+3", "W+6.5" or "Draw"
+    pub result: String,
+}
+
+impl Game {
+    pub fn score(&self) -> Score {
+        let board = self.scoring_board();
+        let territory = self.territory_map();
+
+        // Dead stones are taken off the board as prisoners of the opponent
+        let mut dead_black = 0;
+        let mut dead_white = 0;
+        for &(x, y) in &self.dead_stones {
+            match self.board[y][x] {
+                Player::Black => dead_black += 1,
+                Player::White => dead_white += 1,
+                Player::None => {}
+            }
+        }
+
+        let mut black = PlayerScore {
+            captures: self.prisoners.black + dead_white,
+            ..Default::default()
+        };
+        let mut white = PlayerScore {
+            captures: self.prisoners.white + dead_black,
+            komi: self.komi,
+            handicap: self.rules.handicap_compensation.points(self.handicap),
+            ..Default::default()
+        };
+
+        for (row, owners) in board.iter().zip(&territory) {
+            for (&stone, &owner) in row.iter().zip(owners) {
+                match (stone, owner) {
+                    (Player::Black, _) => black.stones += 1,
+                    (Player::White, _) => white.stones += 1,
+                    (Player::None, Player::Black) => black.territory += 1,
+                    (Player::None, Player::White) => white.territory += 1,
+                    _ => {}
+                }
+            }
+        }
+
+        for s in [&mut black, &mut white] {
+            let points = match self.rules.scoring {
+                ScoringMethod::Area => s.territory + s.stones,
+                ScoringMethod::Territory => s.territory + s.captures,
+            };
+            s.total = points as f32 + s.komi + s.handicap;
+        }
+
+        let diff = black.total - white.total;
+        let (winner, result) = if diff > 0.0 {
+            (Some(Player::Black), format!("B+{}", diff))
+        } else if diff < 0.0 {
+            (Some(Player::White), format!("W+{}", -diff))
+        } else {
+            (None, "Draw".to_string())
+        };
+
+        Score {
+            method: self.rules.scoring,
+            black,
+            white,
+            komi: self.komi,
+            winner,
+            result,
+        }
+    }
+
+    // Owner of every empty point: an empty region bordered by a single color
+    // belongs to that color, anything else is dame (Player::None).
+    // Points under dead stones are counted as empty.
+    pub fn territory_map(&self) -> Vec<Vec<Player>> {
+        let board = self.scoring_board();
+        let size = self.size;
+        let mut owner = vec![vec![Player::None; size]; size];
+        let mut visited = vec![vec![false; size]; size];
+
+        for sy in 0..size {
+            for sx in 0..size {
+                if visited[sy][sx] || board[sy][sx] != Player::None {
+                    continue;
+                }
+
+                let mut region = Vec::new();
+                let mut borders_black = false;
+                let mut borders_white = false;
+                let mut stack = vec![(sx, sy)];
+                visited[sy][sx] = true;
+
+                while let Some((cx, cy)) = stack.pop() {
+                    region.push((cx, cy));
+                    let neighbors = [(0, 1), (0, -1), (1, 0), (-1, 0)];
+                    for (dx, dy) in neighbors.iter() {
+                        let nx = cx as i32 + dx;
+                        let ny = cy as i32 + dy;
+                        if nx < 0 || nx >= size as i32 || ny < 0 || ny >= size as i32 {
+                            continue;
+                        }
+                        let (nx, ny) = (nx as usize, ny as usize);
+                        match board[ny][nx] {
+                            Player::Black => borders_black = true,
+                            Player::White => borders_white = true,
+                            Player::None => {
+                                if !visited[ny][nx] {
+                                    visited[ny][nx] = true;
+                                    stack.push((nx, ny));
+                                }
+                            }
+                        }
+                    }
+                }
+
+                let region_owner = match (borders_black, borders_white) {
+                    (true, false) => Player::Black,
+                    (false, true) => Player::White,
+                    _ => Player::None,
+                };
+                for (rx, ry) in region {
+                    owner[ry][rx] = region_owner;
+                }
+            }
+        }
+        owner
+    }
+
+    fn scoring_board(&self) -> Vec<Vec<Player>> {
+        let mut board = self.board.clone();
+        for &(x, y) in &self.dead_stones {
+            board[y][x] = Player::None;
+        }
+        board
+    }
+
+    // Marks the group at (x, y) dead, or alive again if it already was
+    pub fn toggle_dead(&mut self, x: usize, y: usize) -> Result<(), GameError> {
+        if self.phase != GamePhase::Scoring || self.winner.is_some() || self.is_draw {
+            return Err(GameError::NotInScoringPhase);
+        }
+        if x >= self.size || y >= self.size {
+            return Err(GameError::OutOfBounds);
+        }
+        if self.board[y][x] == Player::None {
+            return Err(GameError::NoStone);
+        }
+
+        let group = self.get_group(&self.board, x, y);
+        if self.dead_stones.contains(&(x, y)) {
+            for stone in group {
+                self.dead_stones.remove(&stone);
+            }
+        } else {
+            self.dead_stones.extend(group);
+        }
+        Ok(())
+    }
+
+    // Both players disagreed on the result, go back to playing
+    pub fn resume_play(&mut self) -> Result<(), GameError> {
+        if self.phase != GamePhase::Scoring || self.winner.is_some() || self.is_draw {
+            return Err(GameError::NotInScoringPhase);
+        }
+        self.phase = GamePhase::Playing;
+        self.consecutive_passes = 0;
+        self.dead_stones.clear();
+        Ok(())
+    }
+
+    pub fn finish_scoring(&mut self) -> Result<Score, GameError> {
+        if self.phase != GamePhase::Scoring || self.winner.is_some() || self.is_draw {
+            return Err(GameError::NotInScoringPhase);
+        }
+        let score = self.score();
+        match score.winner {
+            Some(player) => self.winner = Some(player),
+            None => self.is_draw = true,
+        }
+        Ok(score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{GameSettings, GameType, Handicap};
+    use crate::rules::Rules;
+
+    fn go(rules: Rules) -> Game {
+        Game::new(9, GameType::Go(rules))
+    }
+
+    // Black wall on the third column and White wall on the fourth: 18 points
+    // of territory for Black, 45 for White
+    fn walls(rules: Rules) -> Game {
+        let mut game = go(rules);
+        for y in 0..9 {
+            game.setup_stone(2, y, Player::Black).unwrap();
+            game.setup_stone(3, y, Player::White).unwrap();
+        }
+        game
+    }
+
+    #[test]
+    fn komi_alone_on_an_empty_board() {
+        assert_eq!(go(Rules::Japanese).score().result, "W+6.5");
+        assert_eq!(go(Rules::NewZealand).score().result, "W+7");
+
+        let mut game = go(Rules::Chinese);
+        game.play(4, 4).unwrap();
+        let score = game.score();
+        assert_eq!((score.black.stones, score.black.territory), (1, 80));
+        assert_eq!(score.result, "B+73.5");
+    }
+
+    #[test]
+    fn area_counts_stones_and_territory_counts_captures() {
+        let score = walls(Rules::Chinese).score();
+        assert_eq!(score.method, ScoringMethod::Area);
+        assert_eq!((score.black.total, score.white.total), (27.0, 61.5));
+        assert_eq!(score.result, "W+34.5");
+
+        let mut game = walls(Rules::Japanese);
+        game.prisoners.black = 3;
+        let score = game.score();
+        assert_eq!(score.method, ScoringMethod::Territory);
+        assert_eq!((score.black.total, score.white.total), (21.0, 51.5));
+        assert_eq!(score.result, "W+30.5");
+    }
+
+    #[test]
+    fn handicap_compensation_follows_the_rules() {
+        let result = |rules| {
+            let settings = GameSettings {
+                size: 9,
+                game_type: GameType::Go(rules),
+                komi: None,
+                handicap: Some(Handicap::Fixed(2)),
+                difficulty: Default::default(),
+                opening: None,
+            };
+            let score = Game::with_settings(&settings).unwrap().score();
+            (score.white.handicap, score.result)
+        };
+        assert_eq!(result(Rules::Chinese), (2.0, "B+78.5".to_string()));
+        assert_eq!(result(Rules::Aga), (1.0, "B+79.5".to_string()));
+        assert_eq!(result(Rules::Japanese), (0.0, "B+78.5".to_string()));
+    }
+
+    #[test]
+    fn dead_stones_become_territory_and_prisoners() {
+        let mut game = walls(Rules::Japanese);
+        game.setup_stone(0, 0, Player::White).unwrap();
+        game.setup_stone(0, 1, Player::White).unwrap();
+        assert_eq!(game.toggle_dead(0, 0), Err(GameError::NotInScoringPhase));
+        game.pass().unwrap();
+        game.pass().unwrap();
+
+        // Alive, the white stones make Black's side dame
+        assert_eq!(game.score().black.territory, 0);
+        game.toggle_dead(0, 0).unwrap();
+        assert!(game.dead_stones.contains(&(0, 1)));
+        let score = game.score();
+        assert_eq!((score.black.territory, score.black.captures), (18, 2));
+        assert_eq!(game.territory_map()[1][0], Player::Black);
+        assert_eq!(score.result, "W+31.5");
+
+        game.toggle_dead(0, 1).unwrap();
+        assert!(game.dead_stones.is_empty());
+        assert_eq!(game.toggle_dead(1, 1), Err(GameError::NoStone));
+        game.toggle_dead(0, 0).unwrap();
+        game.resume_play().unwrap();
+        assert!(game.dead_stones.is_empty());
+        assert_eq!(game.phase, GamePhase::Playing);
+    }
+}
@@ -0,0 +1,730 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::game::{Game, GamePhase, GameSettings, GameType, Move, Player};
+use crate::rules::{GomokuRule, Rules};
+use crate::tree::{GameTree, Markup, MarkupKind, TreeNode};
+
+#[derive(Clone, Debug)]
+pub struct SgfProperty {
+    pub ident: String,
+    pub values: Vec<String>,
+    // Byte offset of the property in the source, for error reporting
+    pub offset: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SgfNode {
+    pub properties: Vec<SgfProperty>,
+    // The next node of the main line comes first, then any variations
+    pub children: Vec<SgfNode>,
+}
+
+impl SgfNode {
+    pub fn get(&self, ident: &str) -> Option<&SgfProperty> {
+        self.properties.iter().find(|p| p.ident == ident)
+    }
+
+    fn value(&self, ident: &str) -> Option<&str> {
+        self.get(ident).and_then(|p| p.values.first()).map(|v| v.as_str())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SgfError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SgfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SGF error at offset {}: {}", self.offset, self.message)
+    }
+}
+
+impl std::error::Error for SgfError {}
+
+fn error(offset: usize, message: impl Into<String>) -> SgfError {
+    SgfError {
+        offset,
+        message: message.into(),
+    }
+}
+
+// Writes the game as an SGF FF[4] record with the main line of moves
+pub fn export(game: &Game, black_name: &str, white_name: &str) -> String {
+    let mut out = header(game, black_name, white_name, result(game));
+    push_setup(&mut out, game);
+
+    for record in &game.moves {
+        out.push(';');
+        push_move(&mut out, record.player, record.mv);
+    }
+
+    out.push_str(")\n");
+    out
+}
+
+// Writes a reviewed game with all of its variations, comments and markup
+pub fn export_tree(tree: &GameTree, black_name: &str, white_name: &str) -> String {
+    let root = tree.root();
+    let mut out = header(&root.game, black_name, white_name, result(&tree.main_line_end().game));
+    push_setup(&mut out, &root.game);
+    push_to_play(&mut out, root.to_play);
+    if let (Some(mv), Some(record)) = (root.mv, root.game.moves.last()) {
+        push_move(&mut out, record.player, mv);
+    }
+    push_annotations(&mut out, root);
+    push_variations(&mut out, tree, 0);
+    out.push_str(")\n");
+    out
+}
+
+// Main line as a plain sequence, the alternatives at a branch point in parentheses
+fn push_variations(out: &mut String, tree: &GameTree, mut index: usize) {
+    loop {
+        match tree.node(index).children.as_slice() {
+            [] => return,
+            [only] => {
+                index = *only;
+                push_tree_node(out, tree.node(index));
+            }
+            children => {
+                for &child in children {
+                    out.push('(');
+                    push_tree_node(out, tree.node(child));
+                    push_variations(out, tree, child);
+                    out.push(')');
+                }
+                return;
+            }
+        }
+    }
+}
+
+fn push_tree_node(out: &mut String, node: &TreeNode) {
+    out.push(';');
+    for (color, prop) in [(Player::Black, "AB"), (Player::White, "AW"), (Player::None, "AE")] {
+        let points: Vec<String> = node
+            .setup
+            .iter()
+            .filter(|(_, p)| *p == color)
+            .map(|&((x, y), _)| point(x, y))
+            .collect();
+        push_list(out, prop, &points);
+    }
+    push_to_play(out, node.to_play);
+    if let (Some(mv), Some(record)) = (node.mv, node.game.moves.last()) {
+        push_move(out, record.player, mv);
+    }
+    push_annotations(out, node);
+}
+
+fn push_annotations(out: &mut String, node: &TreeNode) {
+    if !node.comment.is_empty() {
+        push_prop(out, "C", &node.comment);
+    }
+    for (ident, kind) in [
+        ("TR", MarkupKind::Triangle),
+        ("CR", MarkupKind::Circle),
+        ("SQ", MarkupKind::Square),
+        ("MA", MarkupKind::Cross),
+    ] {
+        let points: Vec<String> = node
+            .markup
+            .iter()
+            .filter(|m| m.kind == kind)
+            .map(|m| point(m.point.0, m.point.1))
+            .collect();
+        push_list(out, ident, &points);
+    }
+    let labels: Vec<String> = node
+        .markup
+        .iter()
+        .filter_map(|m| match &m.kind {
+            MarkupKind::Label(text) => Some(format!("{}:{}", point(m.point.0, m.point.1), text)),
+            _ => None,
+        })
+        .collect();
+    push_list(out, "LB", &labels);
+}
+
+fn header(game: &Game, black_name: &str, white_name: &str, result: Option<String>) -> String {
+    let mut out = String::from("(;FF[4]CA[UTF-8]");
+    push_prop(&mut out, "AP", &format!("GoGame:{}", env!("CARGO_PKG_VERSION")));
+
+    let gm = match game.game_type {
+        GameType::Go(_) | GameType::AtariGo => "1",
+        GameType::Gomoku(_) | GameType::Renju | GameType::Connect6 | GameType::Pente => "4",
+    };
+    push_prop(&mut out, "GM", gm);
+    push_prop(&mut out, "SZ", &game.size.to_string());
+
+    if let GameType::Go(rules) = game.game_type {
+        push_prop(&mut out, "KM", &game.komi.to_string());
+        push_prop(&mut out, "RU", rules_name(rules));
+        if game.handicap > 0 {
+            push_prop(&mut out, "HA", &game.handicap.to_string());
+        }
+    }
+    match game.game_type {
+        GameType::Gomoku(rule) => push_prop(&mut out, "RU", gomoku_rule_name(rule)),
+        GameType::Renju => push_prop(&mut out, "RU", "Renju"),
+        GameType::Connect6 => push_prop(&mut out, "RU", "Connect6"),
+        GameType::Pente => push_prop(&mut out, "RU", "Pente"),
+        GameType::AtariGo => push_prop(&mut out, "RU", "AtariGo"),
+        GameType::Go(_) => {}
+    }
+
+    push_prop(&mut out, "PB", black_name);
+    push_prop(&mut out, "PW", white_name);
+    push_prop(&mut out, "DT", &today());
+    if let Some(result) = result {
+        push_prop(&mut out, "RE", &result);
+    }
+    out
+}
+
+fn push_setup(out: &mut String, game: &Game) {
+    for (color, prop) in [(Player::Black, "AB"), (Player::White, "AW")] {
+        let points: Vec<String> = game
+            .setup_stones
+            .iter()
+            .filter(|(_, p)| *p == color)
+            .map(|&((x, y), _)| point(x, y))
+            .collect();
+        push_list(out, prop, &points);
+    }
+}
+
+fn push_to_play(out: &mut String, to_play: Option<Player>) {
+    match to_play {
+        Some(Player::Black) => push_prop(out, "PL", "B"),
+        Some(Player::White) => push_prop(out, "PL", "W"),
+        _ => {}
+    }
+}
+
+fn push_move(out: &mut String, player: Player, mv: Move) {
+    let color = if player == Player::Black { "B" } else { "W" };
+    let value = match mv {
+        Move::Place(x, y) => point(x, y),
+        Move::Pass => String::new(),
+    };
+    push_prop(out, color, &value);
+}
+
+// A property with several values, left out entirely when there are none
+fn push_list(out: &mut String, name: &str, values: &[String]) {
+    if values.is_empty() {
+        return;
+    }
+    out.push_str(name);
+    for value in values {
+        out.push('[');
+        for c in value.chars() {
+            if c == ']' || c == '\\' {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+        out.push(']');
+    }
+}
+
+fn push_prop(out: &mut String, name: &str, value: &str) {
+    push_list(out, name, &[value.to_string()]);
+}
+
+// SGF points are two letters, column then row, starting at 'a'
+pub(crate) fn point(x: usize, y: usize) -> String {
+    let letter = |i: usize| (b'a' + i as u8) as char;
+    format!("{}{}", letter(x), letter(y))
+}
+
+fn gomoku_rule_name(rule: GomokuRule) -> &'static str {
+    match rule {
+        GomokuRule::Freestyle => "Freestyle",
+        GomokuRule::Standard => "Standard",
+        GomokuRule::Caro => "Caro",
+    }
+}
+
+fn rules_name(rules: Rules) -> &'static str {
+    match rules {
+        Rules::Chinese => "Chinese",
+        Rules::Japanese => "Japanese",
+        Rules::Aga => "AGA",
+        Rules::NewZealand => "NZ",
+        Rules::TrompTaylor => "Tromp-Taylor",
+    }
+}
+
+fn result(game: &Game) -> Option<String> {
+    if game.is_draw {
+        return Some("0".to_string());
+    }
+    let winner = game.winner?;
+    let color = if winner == Player::Black { "B" } else { "W" };
+
+    if game.game_type.is_go() {
+        // A Go game only ends outside of scoring by resignation
+        if game.phase == GamePhase::Scoring {
+            Some(game.score().result)
+        } else {
+            Some(format!("{}+R", color))
+        }
+    } else {
+        Some(format!("{}+", color))
+    }
+}
+
+// Current UTC date as YYYY-MM-DD
+fn today() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let days = (secs / 86_400) as i64;
+
+    // Days since 1970-01-01 to a civil date (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// Parses the first game tree of an SGF collection
+pub fn parse(input: &str) -> Result<SgfNode, SgfError> {
+    let mut parser = Parser {
+        src: input.as_bytes(),
+        pos: 0,
+    };
+    // Tolerate a byte order mark and junk before the first tree, as other tools do
+    while parser.pos < parser.src.len() && parser.src[parser.pos] != b'(' {
+        parser.pos += 1;
+    }
+    parser.game_tree()
+}
+
+struct Parser<'a> {
+    src: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.src.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(c) if c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), SgfError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(found) if found == c => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(found) => Err(error(
+                self.pos,
+                format!("Expected '{}' but found '{}'", c as char, found as char),
+            )),
+            None => Err(error(self.pos, format!("Expected '{}' but reached end of input", c as char))),
+        }
+    }
+
+    fn game_tree(&mut self) -> Result<SgfNode, SgfError> {
+        self.expect(b'(')?;
+
+        let mut sequence = Vec::new();
+        self.skip_whitespace();
+        while self.peek() == Some(b';') {
+            sequence.push(self.node()?);
+            self.skip_whitespace();
+        }
+        if sequence.is_empty() {
+            return Err(error(self.pos, "Game tree must start with a node (';')"));
+        }
+
+        let mut variations = Vec::new();
+        while self.peek() == Some(b'(') {
+            variations.push(self.game_tree()?);
+            self.skip_whitespace();
+        }
+        self.expect(b')')?;
+
+        // Chain the sequence so each node's first child is the next one
+        let mut last = sequence.pop().unwrap();
+        last.children = variations;
+        while let Some(mut node) = sequence.pop() {
+            node.children = vec![last];
+            last = node;
+        }
+        Ok(last)
+    }
+
+    fn node(&mut self) -> Result<SgfNode, SgfError> {
+        self.expect(b';')?;
+        let mut node = SgfNode::default();
+
+        loop {
+            self.skip_whitespace();
+            let offset = self.pos;
+            let mut ident = String::new();
+            while let Some(c) = self.peek() {
+                if !c.is_ascii_alphabetic() {
+                    break;
+                }
+                // FF[3] allowed lowercase letters in identifiers, they carry no meaning
+                if c.is_ascii_uppercase() {
+                    ident.push(c as char);
+                }
+                self.pos += 1;
+            }
+            if self.pos == offset {
+                break;
+            }
+            if ident.is_empty() {
+                return Err(error(offset, "Property identifier must contain an uppercase letter"));
+            }
+
+            let mut values = Vec::new();
+            self.skip_whitespace();
+            while self.peek() == Some(b'[') {
+                values.push(self.value()?);
+                self.skip_whitespace();
+            }
+            if values.is_empty() {
+                return Err(error(self.pos, format!("Property {} has no value", ident)));
+            }
+            node.properties.push(SgfProperty {
+                ident,
+                values,
+                offset,
+            });
+        }
+        Ok(node)
+    }
+
+    fn value(&mut self) -> Result<String, SgfError> {
+        let start = self.pos;
+        self.expect(b'[')?;
+        let mut bytes = Vec::new();
+
+        loop {
+            match self.peek() {
+                None => return Err(error(start, "Unterminated property value")),
+                Some(b']') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        None => return Err(error(start, "Unterminated property value")),
+                        // Escaped line break is a soft line break and disappears
+                        Some(b'\n') | Some(b'\r') => {
+                            let first = self.peek();
+                            self.pos += 1;
+                            let second = self.peek();
+                            if matches!((first, second), (Some(b'\n'), Some(b'\r')) | (Some(b'\r'), Some(b'\n'))) {
+                                self.pos += 1;
+                            }
+                        }
+                        Some(c) => {
+                            bytes.push(c);
+                            self.pos += 1;
+                        }
+                    }
+                }
+                Some(c) => {
+                    bytes.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+
+        // Only ASCII backslashes were removed, so the bytes are still valid UTF-8
+        String::from_utf8(bytes).map_err(|_| error(start, "Property value is not valid UTF-8"))
+    }
+}
+
+fn is_renju(rules: &str) -> bool {
+    let rules = rules.trim().to_ascii_lowercase();
+    rules.starts_with("renju") || rules == "rif"
+}
+
+fn is_pente(rules: &str) -> bool {
+    let rules = rules.trim().to_ascii_lowercase();
+    rules == "pente" || rules.starts_with("ninuki")
+}
+
+fn is_atari_go(rules: &str) -> bool {
+    let rules = rules.trim().to_ascii_lowercase();
+    rules.starts_with("atari") || rules.starts_with("capture")
+}
+
+// Builds the starting position from the root node's game information
+pub(crate) fn game_from_root(root: &SgfNode) -> Result<Game, SgfError> {
+    // A property left out is reported at the start of the root node
+    let start = root.properties.first().map_or(0, |p| p.offset);
+    let offset_of = |ident: &str| root.get(ident).map_or(start, |p| p.offset);
+
+    let rules = root.value("RU").and_then(Rules::from_name).unwrap_or(Rules::Chinese);
+    let game_type = match root.value("GM").unwrap_or("1") {
+        "1" if root.value("RU").is_some_and(is_atari_go) => GameType::AtariGo,
+        "1" => GameType::Go(rules),
+        // Renju, Connect6 and Pente share the game number and are told apart by their rules
+        "4" if root.value("RU").is_some_and(is_renju) => GameType::Renju,
+        "4" if root.value("RU").is_some_and(|ru| ru.trim().eq_ignore_ascii_case("connect6")) => GameType::Connect6,
+        "4" if root.value("RU").is_some_and(is_pente) => GameType::Pente,
+        "4" => GameType::Gomoku(
+            root.value("RU").and_then(GomokuRule::from_name).unwrap_or(GomokuRule::Freestyle),
+        ),
+        other => return Err(error(offset_of("GM"), format!("Unsupported game GM[{}]", other))),
+    };
+
+    let size = match root.value("SZ") {
+        None => if matches!(game_type, GameType::Go(_) | GameType::AtariGo | GameType::Connect6 | GameType::Pente) { 19 } else { 15 },
+        Some(v) => v
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| error(offset_of("SZ"), format!("Unsupported board size SZ[{}]", v)))?,
+    };
+    if !(2..=26).contains(&size) {
+        return Err(error(offset_of("SZ"), format!("Board size {} is out of range", size)));
+    }
+
+    let komi = match root.value("KM") {
+        Some(v) => Some(
+            v.trim()
+                .parse::<f32>()
+                .map_err(|_| error(offset_of("KM"), format!("Invalid komi KM[{}]", v)))?,
+        ),
+        None => None,
+    };
+
+    let mut game = Game::with_settings(&GameSettings {
+        size,
+        game_type,
+        komi,
+        handicap: None,
+        difficulty: Default::default(),
+        opening: None,
+    })
+    .map_err(|e| error(offset_of("GM"), e.to_string()))?;
+
+    if let Some(v) = root.value("HA") {
+        game.handicap = v
+            .trim()
+            .parse()
+            .map_err(|_| error(offset_of("HA"), format!("Invalid handicap HA[{}]", v)))?;
+    }
+    Ok(game)
+}
+
+// Applies setup stones and the move of one node, returning the move played
+pub(crate) fn apply_node(game: &mut Game, node: &SgfNode) -> Result<Option<Move>, SgfError> {
+    for (offset, stones) in setup_properties(node, game.size)? {
+        for ((x, y), color) in stones {
+            game.setup_stone(x, y, color).map_err(|e| error(offset, e.to_string()))?;
+        }
+    }
+    if let Some(player) = to_play(node)? {
+        game.current_turn = player;
+    }
+
+    let mut played = None;
+    for prop in &node.properties {
+        let color = match prop.ident.as_str() {
+            "B" => Player::Black,
+            "W" => Player::White,
+            _ => continue,
+        };
+        if played.is_some() {
+            return Err(error(prop.offset, "Node contains more than one move"));
+        }
+
+        // Play may go on after two passes, as it does when the players dispute the dead stones
+        if game.phase == GamePhase::Scoring {
+            game.resume_play().map_err(|e| error(prop.offset, e.to_string()))?;
+        }
+        game.current_turn = color;
+        let value = prop.values[0].trim();
+        let is_pass = value.is_empty() || (value == "tt" && game.size <= 19);
+        let mv = if is_pass {
+            game.pass().map_err(|e| error(prop.offset, format!("Illegal pass: {}", e)))?;
+            Move::Pass
+        } else {
+            let (x, y) = parse_point(value, game.size, prop.offset)?;
+            game.play(x, y).map_err(|e| error(prop.offset, format!("Illegal move {}[{}]: {}", prop.ident, value, e)))?;
+            Move::Place(x, y)
+        };
+        played = Some(mv);
+    }
+    Ok(played)
+}
+
+// Points with the colour a setup property gives them, Player::None for AE
+pub(crate) type SetupStones = Vec<((usize, usize), Player)>;
+
+// Stones that the AB, AW and AE properties of one node add or clear
+pub(crate) fn setup_stones(node: &SgfNode, size: usize) -> Result<SetupStones, SgfError> {
+    Ok(setup_properties(node, size)?.into_iter().flat_map(|(_, stones)| stones).collect())
+}
+
+// The same, grouped by property with the offset of each
+fn setup_properties(node: &SgfNode, size: usize) -> Result<Vec<(usize, SetupStones)>, SgfError> {
+    let mut properties = Vec::new();
+    for prop in &node.properties {
+        let color = match prop.ident.as_str() {
+            "AB" => Player::Black,
+            "AW" => Player::White,
+            "AE" => Player::None,
+            _ => continue,
+        };
+        let mut stones = Vec::new();
+        for value in &prop.values {
+            for p in point_list(value, size, prop.offset)? {
+                stones.push((p, color));
+            }
+        }
+        properties.push((prop.offset, stones));
+    }
+    Ok(properties)
+}
+
+// The player that the node's PL property puts to move
+pub(crate) fn to_play(node: &SgfNode) -> Result<Option<Player>, SgfError> {
+    node.get("PL").map(|pl| parse_color(&pl.values[0], pl.offset)).transpose()
+}
+
+// Triangles, circles, squares, crosses and labels of one node
+pub(crate) fn markup(node: &SgfNode, size: usize) -> Result<Vec<Markup>, SgfError> {
+    let mut markup = Vec::new();
+    for prop in &node.properties {
+        let kind = match prop.ident.as_str() {
+            "TR" => MarkupKind::Triangle,
+            "CR" => MarkupKind::Circle,
+            "SQ" => MarkupKind::Square,
+            "MA" => MarkupKind::Cross,
+            "LB" => {
+                for value in &prop.values {
+                    let (p, text) = value
+                        .split_once(':')
+                        .ok_or_else(|| error(prop.offset, format!("Invalid label [{}]", value)))?;
+                    markup.push(Markup {
+                        point: parse_point(p.trim(), size, prop.offset)?,
+                        kind: MarkupKind::Label(text.to_string()),
+                    });
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        for value in &prop.values {
+            for point in point_list(value, size, prop.offset)? {
+                markup.push(Markup {
+                    point,
+                    kind: kind.clone(),
+                });
+            }
+        }
+    }
+    Ok(markup)
+}
+
+fn parse_color(value: &str, offset: usize) -> Result<Player, SgfError> {
+    match value.trim() {
+        "B" | "b" => Ok(Player::Black),
+        "W" | "w" => Ok(Player::White),
+        other => Err(error(offset, format!("Invalid color '{}'", other))),
+    }
+}
+
+fn parse_point(value: &str, size: usize, offset: usize) -> Result<(usize, usize), SgfError> {
+    let bytes = value.as_bytes();
+    let coord = |c: u8| {
+        if c.is_ascii_lowercase() && ((c - b'a') as usize) < size {
+            Some((c - b'a') as usize)
+        } else {
+            None
+        }
+    };
+    match bytes {
+        [cx, cy] => match (coord(*cx), coord(*cy)) {
+            (Some(x), Some(y)) => Ok((x, y)),
+            _ => Err(error(offset, format!("Point [{}] is off the board", value))),
+        },
+        _ => Err(error(offset, format!("Invalid point [{}]", value))),
+    }
+}
+
+// A single point or a compressed rectangle such as "aa:cc"
+fn point_list(value: &str, size: usize, offset: usize) -> Result<Vec<(usize, usize)>, SgfError> {
+    match value.split_once(':') {
+        None => Ok(vec![parse_point(value.trim(), size, offset)?]),
+        Some((from, to)) => {
+            let (x1, y1) = parse_point(from.trim(), size, offset)?;
+            let (x2, y2) = parse_point(to.trim(), size, offset)?;
+            let mut points = Vec::new();
+            for y in y1.min(y2)..=y1.max(y2) {
+                for x in x1.min(x2)..=x1.max(x2) {
+                    points.push((x, y));
+                }
+            }
+            Ok(points)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn move_after_two_passes_resumes_play() {
+        let tree = GameTree::from_sgf("(;GM[1]FF[4]SZ[9];B[];W[];B[dd])").unwrap();
+        let game = &tree.main_line_end().game;
+        assert_eq!(game.phase, GamePhase::Playing);
+        assert_eq!(game.board[3][3], Player::Black);
+        assert_eq!(game.current_turn, Player::White);
+        assert_eq!(game.consecutive_passes, 0);
+    }
+
+    // Board and player to move at every node, in the order the variations are written
+    fn positions(tree: &GameTree) -> Vec<(Vec<Vec<Player>>, Player)> {
+        let mut out = Vec::new();
+        let mut pending = vec![0];
+        while let Some(index) = pending.pop() {
+            let node = tree.node(index);
+            out.push((node.game.board.clone(), node.game.current_turn));
+            pending.extend(node.children.iter().rev());
+        }
+        out
+    }
+
+    #[test]
+    fn tree_round_trip_keeps_setup_and_root_move() {
+        let record = "(;GM[1]FF[4]SZ[9]AB[aa]B[cc];W[dd](;AB[ee][ff]AE[cc]PL[W];W[gg])(;B[hh]))";
+        let tree = GameTree::from_sgf(record).unwrap();
+        let exported = export_tree(&tree, "Black", "White");
+        assert!(exported.contains("AB[ee][ff]AE[cc]PL[W]"), "{}", exported);
+        let again = GameTree::from_sgf(&exported).unwrap();
+        assert_eq!(again.root().mv, Some(Move::Place(2, 2)));
+        assert_eq!(positions(&again), positions(&tree));
+        assert_eq!(export_tree(&again, "Black", "White"), exported);
+    }
+}
@@ -0,0 +1,39 @@
+// Helpers shared by the unit tests of the engine protocols
+
+use std::io::{self, BufRead, Cursor, Write};
+use std::sync::{Arc, Mutex};
+
+// Collects what is written to an engine; clones share the same buffer
+#[derive(Clone, Default)]
+pub(crate) struct Sent(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Sent {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Sent {
+    // Lines written since the last call
+    pub(crate) fn take(&self) -> Vec<String> {
+        let bytes = std::mem::take(&mut *self.0.lock().unwrap());
+        String::from_utf8(bytes).unwrap().lines().map(str::to_string).collect()
+    }
+}
+
+// What a fake engine answers, in order
+pub(crate) fn script(text: &str) -> Box<dyn BufRead + Send> {
+    Box::new(Cursor::new(text.as_bytes().to_vec()))
+}
+
+// Feeds the script to a protocol loop and returns everything it wrote
+pub(crate) fn transcript(script: &str, run: impl FnOnce(Cursor<&str>, &mut Vec<u8>) -> io::Result<()>) -> String {
+    let mut output = Vec::new();
+    run(Cursor::new(script), &mut output).unwrap();
+    String::from_utf8(output).unwrap()
+}
@@ -0,0 +1,381 @@
+use serde::{Deserialize, Serialize};
+
+use crate::game::{Game, GameError, Move, Player};
+use crate::sgf::{self, SgfError};
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum MarkupKind {
+    Triangle,
+    Circle,
+    Square,
+    Cross,
+    Label(String),
+}
+
+// Annotation drawn on one point of a reviewed position
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Markup {
+    pub point: (usize, usize),
+    pub kind: MarkupKind,
+}
+
+#[derive(Clone, Debug)]
+pub struct TreeNode {
+    // Position after this node's setup and move
+    pub game: Game,
+    pub mv: Option<Move>,
+    // Stones the node's setup properties add, or clear where the colour is
+    // Player::None, and the player they put to move
+    pub setup: Vec<((usize, usize), Player)>,
+    pub to_play: Option<Player>,
+    pub comment: String,
+    pub markup: Vec<Markup>,
+    pub parent: Option<usize>,
+    // The first child continues the main line, the rest are variations
+    pub children: Vec<usize>,
+}
+
+// A game record with variations, stored as an arena of positions
+#[derive(Clone, Debug)]
+pub struct GameTree {
+    nodes: Vec<TreeNode>,
+    current: usize,
+}
+
+impl GameTree {
+    pub fn new(game: Game) -> Self {
+        GameTree {
+            nodes: vec![TreeNode {
+                game,
+                mv: None,
+                setup: Vec::new(),
+                to_play: None,
+                comment: String::new(),
+                markup: Vec::new(),
+                parent: None,
+                children: Vec::new(),
+            }],
+            current: 0,
+        }
+    }
+
+    pub fn from_sgf(input: &str) -> Result<Self, SgfError> {
+        let root = sgf::parse(input)?;
+        let mut game = sgf::game_from_root(&root)?;
+        let mv = sgf::apply_node(&mut game, &root)?;
+
+        let mut tree = GameTree::new(game);
+        tree.nodes[0].mv = mv;
+        tree.nodes[0].setup = sgf::setup_stones(&root, tree.nodes[0].game.size)?;
+        tree.nodes[0].to_play = sgf::to_play(&root)?;
+        tree.nodes[0].comment = comment(&root);
+        tree.nodes[0].markup = sgf::markup(&root, tree.nodes[0].game.size)?;
+
+        // Walk the SGF tree without recursion, long main lines are common
+        let mut pending: Vec<_> = root.children.iter().rev().map(|child| (child, 0)).collect();
+        while let Some((sgf_node, parent)) = pending.pop() {
+            let mut game = tree.nodes[parent].game.clone();
+            let mv = sgf::apply_node(&mut game, sgf_node)?;
+            let markup = sgf::markup(sgf_node, game.size)?;
+            let setup = sgf::setup_stones(sgf_node, game.size)?;
+            let to_play = sgf::to_play(sgf_node)?;
+            let index = tree.nodes.len();
+            tree.nodes.push(TreeNode {
+                game,
+                mv,
+                setup,
+                to_play,
+                comment: comment(sgf_node),
+                markup,
+                parent: Some(parent),
+                children: Vec::new(),
+            });
+            tree.nodes[parent].children.push(index);
+            pending.extend(sgf_node.children.iter().rev().map(|child| (child, index)));
+        }
+        Ok(tree)
+    }
+
+    // Review of a game in progress, with one node per move already played
+    pub fn from_game(game: &Game) -> Self {
+        let mut start = game.clone();
+        start.winner = None;
+        start.is_draw = false;
+        while start.undo().is_ok() {}
+        start.redo_stack.clear();
+
+        // Undo stops at the end of an opening protocol, whose moves stay in the start position
+        let first = start.moves.len();
+        let mut tree = GameTree::new(start);
+        for record in &game.moves[first..] {
+            if tree.play(record.mv).is_err() {
+                break;
+            }
+        }
+        tree
+    }
+
+    pub fn root(&self) -> &TreeNode {
+        &self.nodes[0]
+    }
+
+    pub fn node(&self, index: usize) -> &TreeNode {
+        &self.nodes[index]
+    }
+
+    pub fn current(&self) -> &TreeNode {
+        &self.nodes[self.current]
+    }
+
+    pub fn game(&self) -> &Game {
+        &self.current().game
+    }
+
+    // Number of moves from the root to the current node
+    pub fn move_number(&self) -> usize {
+        let mut count = 0;
+        let mut node = self.current;
+        while let Some(parent) = self.nodes[node].parent {
+            if self.nodes[node].mv.is_some() {
+                count += 1;
+            }
+            node = parent;
+        }
+        count
+    }
+
+    // Index of the current node among its siblings, and how many there are
+    pub fn variation(&self) -> (usize, usize) {
+        match self.current().parent {
+            Some(parent) => {
+                let siblings = &self.nodes[parent].children;
+                let index = siblings.iter().position(|&n| n == self.current).unwrap_or(0);
+                (index, siblings.len())
+            }
+            None => (0, 1),
+        }
+    }
+
+    // Whether the current node is reached by first children only
+    pub fn on_main_line(&self) -> bool {
+        let mut node = self.current;
+        while let Some(parent) = self.nodes[node].parent {
+            if self.nodes[parent].children.first() != Some(&node) {
+                return false;
+            }
+            node = parent;
+        }
+        true
+    }
+
+    // Moves leading to each child, main line first
+    pub fn next_moves(&self) -> Vec<Option<Move>> {
+        self.current().children.iter().map(|&n| self.nodes[n].mv).collect()
+    }
+
+    pub fn forward(&mut self) -> bool {
+        match self.current().children.first() {
+            Some(&child) => {
+                self.current = child;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn back(&mut self) -> bool {
+        match self.current().parent {
+            Some(parent) => {
+                self.current = parent;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn go_to_start(&mut self) {
+        self.current = 0;
+    }
+
+    pub fn go_to_end(&mut self) {
+        while self.forward() {}
+    }
+
+    // Last position of the main line, which decides the recorded result
+    pub fn main_line_end(&self) -> &TreeNode {
+        let mut node = &self.nodes[0];
+        while let Some(&child) = node.children.first() {
+            node = &self.nodes[child];
+        }
+        node
+    }
+
+    // Plays at the current position. An existing continuation with the same move
+    // is followed; otherwise the move starts a new variation.
+    pub fn play(&mut self, mv: Move) -> Result<(), GameError> {
+        let existing = self
+            .current()
+            .children
+            .iter()
+            .copied()
+            .find(|&child| self.nodes[child].mv == Some(mv));
+        if let Some(child) = existing {
+            self.current = child;
+            return Ok(());
+        }
+
+        let mut game = self.game().clone();
+        match mv {
+            Move::Place(x, y) => {
+                game.play(x, y)?;
+            }
+            Move::Pass => game.pass()?,
+        }
+        let index = self.nodes.len();
+        self.nodes.push(TreeNode {
+            game,
+            mv: Some(mv),
+            setup: Vec::new(),
+            to_play: None,
+            comment: String::new(),
+            markup: Vec::new(),
+            parent: Some(self.current),
+            children: Vec::new(),
+        });
+        self.nodes[self.current].children.push(index);
+        self.current = index;
+        Ok(())
+    }
+
+    pub fn set_comment(&mut self, comment: String) {
+        self.nodes[self.current].comment = comment;
+    }
+
+    // Adds the markup, or removes it if the point already carries the same kind
+    pub fn toggle_markup(&mut self, point: (usize, usize), kind: MarkupKind) -> Result<(), GameError> {
+        let node = &mut self.nodes[self.current];
+        if point.0 >= node.game.size || point.1 >= node.game.size {
+            return Err(GameError::OutOfBounds);
+        }
+        let existing = node.markup.iter().position(|m| m.point == point);
+        match existing {
+            Some(i) if node.markup[i].kind == kind => {
+                node.markup.remove(i);
+            }
+            Some(i) => node.markup[i].kind = kind,
+            None => node.markup.push(Markup { point, kind }),
+        }
+        Ok(())
+    }
+
+    // Removes the current node with everything after it and steps back to its parent
+    pub fn delete_variation(&mut self) -> bool {
+        let parent = match self.current().parent {
+            Some(parent) => parent,
+            None => return false,
+        };
+
+        let mut removed = vec![false; self.nodes.len()];
+        let mut pending = vec![self.current];
+        while let Some(index) = pending.pop() {
+            removed[index] = true;
+            pending.extend(&self.nodes[index].children);
+        }
+
+        // Compact the arena and renumber the links of the remaining nodes
+        let mut remap = vec![0; self.nodes.len()];
+        let mut kept = Vec::with_capacity(self.nodes.len());
+        for (index, node) in std::mem::take(&mut self.nodes).into_iter().enumerate() {
+            if !removed[index] {
+                remap[index] = kept.len();
+                kept.push(node);
+            }
+        }
+        for node in &mut kept {
+            node.parent = node.parent.map(|p| remap[p]);
+            node.children.retain(|&c| !removed[c]);
+            for child in &mut node.children {
+                *child = remap[*child];
+            }
+        }
+        self.nodes = kept;
+        self.current = remap[parent];
+        true
+    }
+
+    // Makes the line through the current node the main line at the nearest
+    // branch point above it where it is a side variation. False if the current
+    // node is on the main line already.
+    pub fn promote_variation(&mut self) -> bool {
+        let mut node = self.current;
+        while let Some(parent) = self.nodes[node].parent {
+            let children = &mut self.nodes[parent].children;
+            let index = children.iter().position(|&n| n == node).unwrap_or(0);
+            if index > 0 {
+                children.remove(index);
+                children.insert(0, node);
+                return true;
+            }
+            node = parent;
+        }
+        false
+    }
+
+    // Moves sideways to another variation of the current move
+    pub fn switch_branch(&mut self, index: usize) -> bool {
+        let sibling = self
+            .current()
+            .parent
+            .and_then(|parent| self.nodes[parent].children.get(index).copied());
+        match sibling {
+            Some(node) => {
+                self.current = node;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn comment(node: &sgf::SgfNode) -> String {
+    node.get("C").map(|p| p.values.join("\n")).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::GameType;
+    use crate::rules::Rules;
+
+    #[test]
+    fn promote_from_deep_in_a_side_line() {
+        let (a, b, c, d) = (Move::Place(2, 2), Move::Place(6, 6), Move::Place(2, 6), Move::Place(6, 2));
+        let mut tree = GameTree::new(Game::new(9, GameType::Go(Rules::Chinese)));
+        tree.play(a).unwrap();
+        tree.play(b).unwrap();
+        tree.go_to_start();
+        tree.play(c).unwrap();
+        tree.play(d).unwrap();
+
+        // D is the only reply to C, which is the side variation of the first move
+        assert_eq!(tree.variation(), (0, 1));
+        assert!(!tree.on_main_line());
+        assert!(tree.promote_variation());
+        assert!(tree.on_main_line());
+        assert_eq!(tree.current().mv, Some(d));
+        assert_eq!(tree.main_line_end().mv, Some(d));
+        tree.go_to_start();
+        assert_eq!(tree.next_moves(), [Some(c), Some(a)]);
+
+        tree.go_to_end();
+        assert!(!tree.promote_variation());
+
+        // At its own branch point the variation simply swaps places
+        tree.go_to_start();
+        tree.play(a).unwrap();
+        assert_eq!(tree.variation(), (1, 2));
+        assert!(tree.promote_variation());
+        assert_eq!(tree.variation(), (0, 2));
+        assert_eq!(tree.main_line_end().mv, Some(b));
+    }
+}
@@ -1,26 +1,156 @@
 import { useState, useEffect } from "react";
 import { invoke } from "@tauri-apps/api/tauri";
 import { listen } from "@tauri-apps/api/event";
+import { open, save } from "@tauri-apps/api/dialog";
 import "./index.css";
 
 type Player = "None" | "Black" | "White";
-type GameType = "Go" | "Gomoku";
+type GameMode = "Go" | "Gomoku" | "Renju" | "Connect6" | "Pente" | "AtariGo";
+type EngineProtocol = "gtp" | "piskvork";
+type Rules = "Chinese" | "Japanese" | "Aga" | "NewZealand" | "TrompTaylor";
+type GomokuRule = "Freestyle" | "Standard" | "Caro";
+type GameType = { Go: Rules } | { Gomoku: GomokuRule } | "Renju" | "Connect6" | "Pente" | "AtariGo";
+type Move = { Place: [number, number] } | "Pass";
+type GamePhase = "Playing" | "PlacingHandicap" | "Opening" | "Scoring";
+type OpeningRule = "Swap" | "Swap2" | "Soosorv8" | "Taraguchi10";
+type Seat = "First" | "Second";
+type OpeningStep = "Place" | "Choose" | "DeclareOffers" | "Offer" | "Pick";
+type OpeningChoice = "Black" | "White" | "PlaceTwo" | { Offers: number };
+type Handicap = { Fixed: number } | { Free: number };
+type ScoringMethod = "Area" | "Territory";
+type Difficulty = "Beginner" | "Easy" | "Medium" | "Hard" | "Strong";
+
+// Go search budget set by hand over the difficulty's; null keeps the difficulty's value
+interface AiConfig {
+  playouts: number | null;
+  time_limit_ms: number | null;
+}
+
+// Settings kept between sessions, falling back when nothing usable is stored
+const loadSetting = <T,>(key: string, fallback: T): T => {
+  try {
+    const stored = localStorage.getItem(key);
+    return stored === null ? fallback : JSON.parse(stored);
+  } catch {
+    return fallback;
+  }
+};
+const saveSetting = (key: string, value: unknown) => localStorage.setItem(key, JSON.stringify(value));
+
+const DEFAULT_KOMI: Record<Rules, number> = {
+  Chinese: 7.5,
+  Japanese: 6.5,
+  Aga: 7.5,
+  NewZealand: 7,
+  TrompTaylor: 7.5,
+};
+
+interface GameSettings {
+  size: number;
+  game_type: GameType;
+  komi: number | null;
+  handicap: Handicap | null;
+  difficulty: Difficulty;
+  opening: OpeningRule | null;
+}
+
+interface Opening {
+  rule: OpeningRule;
+  step: OpeningStep | null;
+  actor: Seat;
+  black: Seat;
+  offers: [number, number][];
+  offer_count: number;
+  moves: number;
+  two_more: boolean;
+}
+
+interface PlayerScore {
+  territory: number;
+  stones: number;
+  captures: number;
+  komi: number;
+  handicap: number;
+  total: number;
+}
+
+interface Score {
+  method: ScoringMethod;
+  black: PlayerScore;
+  white: PlayerScore;
+  komi: number;
+  winner: Player | null;
+  result: string;
+}
 
 interface GameState {
   board: Player[][];
   current_turn: Player;
-  last_move: [number, number] | null;
+  last_move: Move | null;
+  captured: [number, number][];
+  prisoners: { black: number; white: number };
   winner: Player | null;
   is_draw: boolean;
+  phase: GamePhase;
+  can_undo: boolean;
+  can_redo: boolean;
+  handicap_remaining: number;
+  score: Score | null;
+  dead_stones: [number, number][];
+  territory: Player[][] | null;
+  opening: Opening | null;
+  to_act: Seat;
+  stones_to_place: number;
+}
+
+interface AiProgress {
+  best_move: Move | null;
+  nodes: number;
+  win_rate: number;
+}
+
+type MarkupKind = "Triangle" | "Circle" | "Square" | "Cross" | { Label: string };
+type MarkupTool = "Stone" | "Triangle" | "Circle" | "Square" | "Cross" | "Label";
+
+interface Markup {
+  point: [number, number];
+  kind: MarkupKind;
+}
+
+interface ReviewState {
+  game: GameState;
+  game_type: GameType;
+  comment: string;
+  markup: Markup[];
+  move_number: number;
+  variation: number;
+  variations: number;
+  on_main_line: boolean;
+  next_moves: (Move | null)[];
+}
+
+// The turn an undo request takes back, and the move count when it was asked for
+interface UndoRequest {
+  player: Player;
+  moves: number;
 }
 
 type NetworkMessage = 
   | { Move: [number, number] }
+  | { Turn: [number, number][] }
+  | "Pass"
   | "Resign"
   | "OfferDraw"
   | "AcceptDraw"
   | "RejectDraw"
-  | { Restart: [number, GameType] };
+  | { Restart: GameSettings }
+  | { MarkDead: [number, number] }
+  | "AcceptScore"
+  | "ResumePlay"
+  | { RequestUndo: UndoRequest }
+  | { AcceptUndo: UndoRequest }
+  | "RejectUndo"
+  | { OpeningChoice: OpeningChoice };
 
 function App() {
   const [gameState, setGameState] = useState<GameState | null>(null);
@@ -28,8 +158,33 @@ function App() {
   const [ip, setIp] = useState("127.0.0.1:8080");
   const [port, setPort] = useState("8080");
   const [isAiMode, setIsAiMode] = useState(false);
-  const [gameType, setGameType] = useState<GameType>("Go");
+  const [gameType, setGameType] = useState<GameMode>("Go");
   const [drawOfferedByOpponent, setDrawOfferedByOpponent] = useState(false);
+  const [undoRequestedByOpponent, setUndoRequestedByOpponent] = useState<UndoRequest | null>(null);
+  const [isOnline, setIsOnline] = useState(false);
+  const [rules, setRules] = useState<Rules>("Chinese");
+  const [gomokuRule, setGomokuRule] = useState<GomokuRule>("Freestyle");
+  const [openingRule, setOpeningRule] = useState<OpeningRule | null>(null);
+  const [offerCount, setOfferCount] = useState(2);
+  // First stone of a Connect6 turn, placed for real together with the second
+  const [pendingStone, setPendingStone] = useState<[number, number] | null>(null);
+  const [komi, setKomi] = useState(DEFAULT_KOMI["Chinese"]);
+  const [goSize, setGoSize] = useState(19);
+  const [handicapStones, setHandicapStones] = useState(0);
+  const [freeHandicap, setFreeHandicap] = useState(false);
+  const [review, setReview] = useState<ReviewState | null>(null);
+  const [aiThinking, setAiThinking] = useState(false);
+  const [aiProgress, setAiProgress] = useState<AiProgress | null>(null);
+  // External engines: GTP for Go, Gomocup brains for Gomoku
+  const [engineCommands, setEngineCommands] = useState<Record<EngineProtocol, string>>({
+    gtp: "gnugo --mode gtp",
+    piskvork: "pbrain-embryo",
+  });
+  const [externalEngines, setExternalEngines] = useState<Record<EngineProtocol, string | null>>({ gtp: null, piskvork: null });
+  const [difficulty, setDifficulty] = useState<Difficulty>(() => loadSetting("difficulty", "Medium"));
+  const [aiConfig, setAiConfig] = useState<AiConfig>(() => loadSetting("aiConfig", { playouts: null, time_limit_ms: null }));
+  const [markupTool, setMarkupTool] = useState<MarkupTool>("Stone");
+  const [commentDraft, setCommentDraft] = useState("");
 
   const playSound = (type: "move" | "win" | "lose" | "draw" = "move") => {
     try {
@@ -83,11 +238,21 @@ function App() {
     }
   };
 
-  const startNewGame = (type: GameType) => {
+  const startNewGame = (type: GameMode) => {
     setGameType(type);
-    const size = type === "Go" ? 19 : 15;
+    setReview(null);
+    setPendingStone(null);
+    const size = type === "Go" ? goSize : type === "AtariGo" ? 9 : type === "Connect6" || type === "Pente" ? 19 : 15;
+    const handicap: Handicap | null = handicapStones < 2
+      ? null
+      : freeHandicap ? { Free: handicapStones } : { Fixed: handicapStones };
+    const settings: GameSettings = type === "Go"
+      ? { size, game_type: { Go: rules }, komi, handicap, difficulty, opening: null }
+      : type === "Connect6" || type === "Pente" || type === "AtariGo"
+      ? { size, game_type: type, komi: null, handicap: null, difficulty, opening: null }
+      : { size, game_type: type === "Gomoku" ? { Gomoku: gomokuRule } : "Renju", komi: null, handicap: null, difficulty, opening: openingRule };
     // If connected, we should send restart command
-    invoke<GameState>("handle_game_action", { action: "restart", payload: `${size},${type}` }).then((state) => {
+    invoke<GameState>("handle_game_action", { action: "restart", payload: JSON.stringify(settings) }).then((state) => {
         setGameState(state);
         setStatus("游戏开始");
         setDrawOfferedByOpponent(false);
@@ -96,6 +261,7 @@ function App() {
 
   useEffect(() => {
     startNewGame("Go");
+    invoke("set_ai_config", { config: aiConfig }).catch(e => setStatus(`错误: ${e}`));
 
     const unlisten = listen<NetworkMessage>("network-action", (event: any) => {
       const msg = event.payload;
@@ -109,6 +275,20 @@ function App() {
                 playSound("move");
                 checkGameOver(state);
             });
+      } else if (typeof msg === 'object' && 'Turn' in msg) {
+          invoke<GameState>("apply_remote_turn", { stones: msg.Turn })
+            .then((state) => {
+                setGameState(state);
+                playSound("move");
+                checkGameOver(state);
+            });
+      } else if (msg === "Pass") {
+          invoke<GameState>("apply_remote_pass")
+            .then((state) => {
+                setGameState(state);
+                setStatus("对方停一手");
+                checkGameOver(state);
+            });
       } else if (msg === "Resign") {
           invoke<GameState>("get_state").then(state => {
               setGameState(state);
@@ -121,12 +301,50 @@ function App() {
               setGameState(state);
               checkGameOver(state);
           });
+      } else if (typeof msg === 'object' && 'MarkDead' in msg) {
+          const [x, y] = msg.MarkDead;
+          invoke<GameState>("apply_remote_dead_stone", { x, y }).then(setGameState);
+      } else if (msg === "AcceptScore") {
+          invoke<GameState>("apply_remote_score_action", { action: "accept_score" }).then(state => {
+              setGameState(state);
+              if (!state.winner && !state.is_draw) setStatus("对方已确认数子结果");
+              checkGameOver(state);
+          });
+      } else if (typeof msg === 'object' && 'RequestUndo' in msg) {
+          setUndoRequestedByOpponent(msg.RequestUndo);
+      } else if (typeof msg === 'object' && 'AcceptUndo' in msg) {
+          invoke<GameState>("apply_remote_undo", { request: msg.AcceptUndo }).then(state => {
+              setGameState(state);
+              setStatus("对方同意悔棋");
+          }).catch(e => setStatus(`错误: ${e}`));
+      } else if (msg === "RejectUndo") {
+          setStatus("对方拒绝了悔棋");
+      } else if (msg === "ResumePlay") {
+          invoke<GameState>("apply_remote_score_action", { action: "resume_play" }).then(state => {
+              setGameState(state);
+              setStatus("对方要求继续对局");
+          });
       } else if (msg === "RejectDraw") {
           setStatus("对方拒绝了求和");
+      } else if (typeof msg === 'object' && 'OpeningChoice' in msg) {
+          invoke<GameState>("apply_remote_opening_choice", { choice: msg.OpeningChoice }).then(state => {
+              setGameState(state);
+              checkGameOver(state);
+          }).catch(e => setStatus(`错误: ${e}`));
       } else if (typeof msg === 'object' && 'Restart' in msg) {
-          const [size, type] = msg.Restart;
-          setGameType(type);
-          invoke<GameState>("get_state").then(state => {
+          const settings = msg.Restart;
+          if (typeof settings.game_type === "object" && "Go" in settings.game_type) {
+              setGameType("Go");
+              setRules(settings.game_type.Go);
+          } else if (typeof settings.game_type === "object") {
+              setGameType("Gomoku");
+              setGomokuRule(settings.game_type.Gomoku);
+          } else {
+              setGameType(settings.game_type);
+          }
+          setOpeningRule(settings.opening ?? null);
+          setPendingStone(null);
+          invoke<GameState>("new_game", { settings }).then(state => {
               setGameState(state);
               setStatus("游戏重新开始");
               setDrawOfferedByOpponent(false);
@@ -134,14 +352,27 @@ function App() {
       }
     });
 
+    const unlistenProgress = listen<AiProgress>("ai-progress", (event) => setAiProgress(event.payload));
+    const unlistenNetworkError = listen<string>("network-error", (event) => setStatus(`网络错误: ${event.payload}`));
+    const unlistenDisconnected = listen("network-disconnected", () => {
+      setIsOnline(false);
+      setStatus("连接已断开");
+    });
+
     return () => {
       unlisten.then((f: any) => f());
+      unlistenProgress.then((f: any) => f());
+      unlistenNetworkError.then((f: any) => f());
+      unlistenDisconnected.then((f: any) => f());
     };
   }, []);
 
   const checkGameOver = (state: GameState) => {
-      if (state.winner) {
-          setStatus(`游戏结束! ${state.winner === "Black" ? "黑方" : "白方"} 获胜!`);
+      if (state.phase === "Scoring" && !state.winner && !state.is_draw) {
+          setStatus("双方停一手, 进入数子阶段");
+      } else if (state.winner) {
+          const result = state.score ? ` (${state.score.result})` : "";
+          setStatus(`游戏结束! ${state.winner === "Black" ? "黑方" : "白方"} 获胜!${result}`);
           playSound("win");
       } else if (state.is_draw) {
           setStatus("游戏结束! 平局!");
@@ -150,32 +381,172 @@ function App() {
   };
 
   const handleCellClick = async (x: number, y: number) => {
-    if (!gameState) return;
+    if (!gameState || aiThinking) return;
+    if (review) {
+      await handleReviewClick(x, y);
+      return;
+    }
     if (gameState.winner || gameState.is_draw) return;
 
+    if (gameState.phase === "Scoring") {
+      try {
+        setGameState(await invoke<GameState>("toggle_dead_stone", { x, y }));
+      } catch (e) {
+        setStatus(`错误: ${e}`);
+      }
+      return;
+    }
+
+    // Connect6: hold the first stone of a two-stone turn until the second is chosen
+    if (gameType === "Connect6" && gameState.stones_to_place === 2 && !pendingStone) {
+      if (gameState.board[y][x] === "None") setPendingStone([x, y]);
+      return;
+    }
+    if (pendingStone && pendingStone[0] === x && pendingStone[1] === y) {
+      setPendingStone(null);
+      return;
+    }
+
     try {
-      const newState = await invoke<GameState>("play_move", { x, y });
+      const newState = gameType === "Connect6"
+        ? await invoke<GameState>("play_turn", { stones: pendingStone ? [pendingStone, [x, y]] : [[x, y]] })
+        : await invoke<GameState>("play_move", { x, y });
+      setPendingStone(null);
       setGameState(newState);
       setStatus("");
       playSound("move");
 
       checkGameOver(newState);
 
-      if (isAiMode && !newState.winner && !newState.is_draw) {
-        setTimeout(handleAI, 200);
+      if (isAiMode && newState.to_act === "Second" && !newState.winner && !newState.is_draw) {
+        setTimeout(() => handleAI(true), 200);
       }
     } catch (e) {
       setStatus(`错误: ${e}`);
     }
   };
 
-  const handleAI = async () => {
+  // `asSecond`: the AI plays the second seat and keeps going while it is its
+  // turn, which during an opening can take several actions in a row
+  const handleAI = async (asSecond = false) => {
+    setAiThinking(true);
+    setAiProgress(null);
+    setStatus("AI 思考中...");
     try {
       const newState = await invoke<GameState>("play_ai");
       setGameState(newState);
-      setStatus("AI 已落子");
+      setStatus(newState.last_move === "Pass" ? "AI 停一手" : "AI 已落子");
       playSound("move");
       checkGameOver(newState);
+      if (asSecond && newState.to_act === "Second" && !newState.winner && !newState.is_draw) {
+        setTimeout(() => handleAI(true), 200);
+      }
+    } catch (e) {
+      setStatus(`错误: ${e}`);
+    } finally {
+      setAiThinking(false);
+      setAiProgress(null);
+    }
+  };
+
+  // Gomoku and Renju share the Gomocup brain
+  const engineProtocol: EngineProtocol = gameType === "Go" ? "gtp" : "piskvork";
+
+  const startExternalEngine = async () => {
+    const protocol = engineProtocol;
+    setStatus("正在启动引擎...");
+    try {
+      const name = await invoke<string>(`start_${protocol}_engine`, { commandLine: engineCommands[protocol] });
+      setExternalEngines(engines => ({ ...engines, [protocol]: name }));
+      setStatus(`已连接引擎: ${name}`);
+    } catch (e) {
+      setStatus(`错误: ${e}`);
+    }
+  };
+
+  const stopExternalEngine = async () => {
+    const protocol = engineProtocol;
+    try {
+      await invoke(`stop_${protocol}_engine`);
+      setExternalEngines(engines => ({ ...engines, [protocol]: null }));
+      setStatus("已改用内置 AI");
+    } catch (e) {
+      setStatus(`错误: ${e}`);
+    }
+  };
+
+  const cancelAI = (moveNow: boolean) => {
+    invoke("cancel_ai", { moveNow }).catch(e => setStatus(`错误: ${e}`));
+  };
+
+  const describeProgress = (progress: AiProgress) => {
+    const best = progress.best_move === null
+      ? "-"
+      : progress.best_move === "Pass" ? "停一手" : `(${progress.best_move.Place[0] + 1}, ${progress.best_move.Place[1] + 1})`;
+    return `最佳 ${best} · 节点 ${progress.nodes} · 胜率 ${Math.round(progress.win_rate * 100)}%`;
+  };
+
+  const updateDifficulty = (level: Difficulty) => {
+    setDifficulty(level);
+    saveSetting("difficulty", level);
+    invoke("set_difficulty", { difficulty: level }).catch(e => setStatus(`错误: ${e}`));
+  };
+
+  const updateAiConfig = (config: AiConfig) => {
+    setAiConfig(config);
+    saveSetting("aiConfig", config);
+    invoke("set_ai_config", { config }).catch(e => setStatus(`错误: ${e}`));
+  };
+
+  const handlePass = async () => {
+    if (review) {
+      await reviewCommand("review_pass");
+      return;
+    }
+    if (!gameState || gameState.winner || gameState.is_draw) return;
+
+    try {
+      const newState = await invoke<GameState>("pass");
+      setGameState(newState);
+      setStatus("已停一手");
+      checkGameOver(newState);
+
+      if (isAiMode && newState.phase === "Playing" && !newState.winner && !newState.is_draw) {
+        setTimeout(() => handleAI(), 200);
+      }
+    } catch (e) {
+      setStatus(`错误: ${e}`);
+    }
+  };
+
+  const handleOpeningChoice = async (choice: OpeningChoice) => {
+    try {
+      const newState = await invoke<GameState>("choose_opening", { choice });
+      setGameState(newState);
+      setStatus("");
+      if (isAiMode && newState.to_act === "Second" && !newState.winner && !newState.is_draw) {
+        setTimeout(() => handleAI(true), 200);
+      }
+    } catch (e) {
+      setStatus(`错误: ${e}`);
+    }
+  };
+
+  const handleAcceptScore = async () => {
+    try {
+      const newState = await invoke<GameState>("handle_game_action", { action: "accept_score", payload: null });
+      setGameState(newState);
+      if (!newState.winner && !newState.is_draw) setStatus("等待对方确认...");
+      checkGameOver(newState);
+    } catch (e) {
+      setStatus(`错误: ${e}`);
+    }
+  };
+
+  const handleResumePlay = async () => {
+    try {
+      setGameState(await invoke<GameState>("handle_game_action", { action: "resume_play", payload: null }));
+      setStatus("继续对局");
     } catch (e) {
       setStatus(`错误: ${e}`);
     }
@@ -208,9 +579,137 @@ function App() {
       setDrawOfferedByOpponent(false);
   };
 
+  const handleUndo = async () => {
+    if (!gameState || !gameState.can_undo) return;
+    try {
+      if (isOnline) {
+        await invoke("handle_game_action", { action: "request_undo", payload: null });
+        setStatus("已发送悔棋请求...");
+        return;
+      }
+      setPendingStone(null);
+      let newState = await invoke<GameState>("undo");
+      // Against the AI take back its reply as well, so it is our turn again
+      if (isAiMode && newState.can_undo) {
+        newState = await invoke<GameState>("undo");
+      }
+      setGameState(newState);
+      setStatus("已悔棋");
+    } catch (e) {
+      setStatus(`错误: ${e}`);
+    }
+  };
+
+  const handleRedo = async () => {
+    try {
+      let newState = await invoke<GameState>("redo");
+      if (isAiMode && newState.can_redo) {
+        newState = await invoke<GameState>("redo");
+      }
+      setGameState(newState);
+      checkGameOver(newState);
+    } catch (e) {
+      setStatus(`错误: ${e}`);
+    }
+  };
+
+  const handleAcceptUndo = async () => {
+    try {
+      const payload = JSON.stringify(undoRequestedByOpponent);
+      setGameState(await invoke<GameState>("handle_game_action", { action: "accept_undo", payload }));
+    } catch (e) {
+      setStatus(`错误: ${e}`);
+    }
+    setUndoRequestedByOpponent(null);
+  };
+
+  const handleRejectUndo = async () => {
+    await invoke("handle_game_action", { action: "reject_undo", payload: null });
+    setUndoRequestedByOpponent(null);
+  };
+
+  const handleExportSgf = async () => {
+    try {
+      const path = await save({ filters: [{ name: "SGF", extensions: ["sgf"] }] });
+      if (!path) return;
+      await invoke("export_sgf", {
+        path,
+        black: isAiMode ? null : "Black",
+        white: isAiMode ? "GoGame AI" : "White",
+      });
+      setStatus("棋谱已保存");
+    } catch (e) {
+      setStatus(`错误: ${e}`);
+    }
+  };
+
+  const showReview = (r: ReviewState) => {
+    setReview(r);
+    setGameState(r.game);
+    if (typeof r.game_type === "object" && "Gomoku" in r.game_type) {
+      setGameType("Gomoku");
+      setGomokuRule(r.game_type.Gomoku);
+    } else {
+      setGameType(typeof r.game_type === "object" ? "Go" : r.game_type);
+    }
+    setCommentDraft(r.comment);
+  };
+
+  const reviewCommand = async (command: string, args?: Record<string, unknown>) => {
+    try {
+      showReview(await invoke<ReviewState>(command, args));
+    } catch (e) {
+      setStatus(`错误: ${e}`);
+    }
+  };
+
+  const handleStartReview = () => reviewCommand("start_review");
+
+  const handleReviewClick = async (x: number, y: number) => {
+    if (!review) return;
+    if (markupTool === "Stone") {
+      await reviewCommand("review_play", { x, y });
+      return;
+    }
+    let kind: MarkupKind;
+    if (markupTool === "Label") {
+      // Next letter that is not used on this position yet
+      const used = review.markup.flatMap(m => typeof m.kind === "object" ? [m.kind.Label] : []);
+      const letter = "ABCDEFGHIJKLMNOPQRSTUVWXYZ".split("").find(l => !used.includes(l)) ?? "?";
+      const existing = review.markup.find(m => m.point[0] === x && m.point[1] === y && typeof m.kind === "object");
+      kind = existing ? existing.kind : { Label: letter };
+    } else {
+      kind = markupTool;
+    }
+    await reviewCommand("toggle_markup", { x, y, kind });
+  };
+
+  const handleLoadSgf = async () => {
+    try {
+      const path = await open({ filters: [{ name: "SGF", extensions: ["sgf"] }] });
+      if (!path || Array.isArray(path)) return;
+      showReview(await invoke<ReviewState>("load_sgf", { path }));
+      setStatus("棋谱已载入");
+    } catch (e) {
+      setStatus(`错误: ${e}`);
+    }
+  };
+
+  const handleNavigate = (action: "start" | "back" | "forward" | "end") =>
+    reviewCommand("navigate_review", { action });
+
+  const handleSwitchVariation = (index: number) => reviewCommand("switch_variation", { index });
+
+  const handleCloseReview = async () => {
+    setGameState(await invoke<GameState>("close_review"));
+    setReview(null);
+    setStatus("从当前局面继续对局");
+  };
+
   const startHost = async () => {
     try {
       const res = await invoke<string>("start_host", { port: parseInt(port) });
+      setIsOnline(true);
       setStatus(res);
     } catch (e) {
       setStatus(`错误: ${e}`);
@@ -220,6 +719,7 @@ function App() {
   const connectHost = async () => {
     try {
       const res = await invoke<string>("connect_to_host", { ip });
+      setIsOnline(true);
       setStatus(res);
     } catch (e) {
       setStatus(`错误: ${e}`);
@@ -243,6 +743,19 @@ function App() {
           </div>
       )}
 
+      {/* Undo Request Modal */}
+      {undoRequestedByOpponent && (
+          <div className="modal-overlay">
+              <div className="modal">
+                  <h3>对方请求悔棋</h3>
+                  <div className="modal-buttons">
+                      <button onClick={handleAcceptUndo}>同意</button>
+                      <button onClick={handleRejectUndo}>拒绝</button>
+                  </div>
+              </div>
+          </div>
+      )}
+
       {/* Game Over Overlay */}
       {(gameState.winner || gameState.is_draw) && (
           <div className="game-over-overlay">
@@ -251,9 +764,13 @@ function App() {
                       <>
                         <h1>{gameState.winner === "Black" ? "黑方" : "白方"} 获胜!</h1>
                         <div className="trophy">🏆</div>
+                        {gameState.score && <ScoreTable score={gameState.score} />}
                       </>
                   ) : (
-                      <h1>平局!</h1>
+                      <>
+                        <h1>平局!</h1>
+                        {gameState.score && <ScoreTable score={gameState.score} />}
+                      </>
                   )}
                   <button onClick={() => startNewGame(gameType)}>重新开始</button>
               </div>
@@ -264,21 +781,159 @@ function App() {
         <h1>Go / Gomoku</h1>
         <div className="controls">
             <div className="game-mode">
-                <button className={gameType === "Go" ? "active" : ""} onClick={() => startNewGame("Go")}>围棋 (19x19)</button>
+                <button className={gameType === "Go" ? "active" : ""} onClick={() => startNewGame("Go")}>围棋 ({goSize}x{goSize})</button>
                 <button className={gameType === "Gomoku" ? "active" : ""} onClick={() => startNewGame("Gomoku")}>五子棋 (15x15)</button>
+                <button className={gameType === "Renju" ? "active" : ""} onClick={() => startNewGame("Renju")}>连珠 (15x15)</button>
+                <button className={gameType === "Connect6" ? "active" : ""} onClick={() => startNewGame("Connect6")}>六子棋 (19x19)</button>
+                <button className={gameType === "Pente" ? "active" : ""} onClick={() => startNewGame("Pente")}>Pente 吃子五子棋 (19x19)</button>
+                <button className={gameType === "AtariGo" ? "active" : ""} onClick={() => startNewGame("AtariGo")}>吃子棋 (9x9)</button>
             </div>
             
+            {gameType === "Go" && (
+                <div className="go-settings">
+                    <label>
+                        贴目
+                        <input type="number" step="0.5" value={komi} onChange={e => setKomi(parseFloat(e.target.value) || 0)} />
+                    </label>
+                    <select value={goSize} onChange={e => setGoSize(parseInt(e.target.value))}>
+                        <option value={9}>9路</option>
+                        <option value={13}>13路</option>
+                        <option value={19}>19路</option>
+                    </select>
+                    <select value={handicapStones} onChange={e => {
+                        const n = parseInt(e.target.value);
+                        setHandicapStones(n);
+                        setKomi(n >= 2 ? 0.5 : DEFAULT_KOMI[rules]);
+                    }}>
+                        <option value={0}>分先</option>
+                        {[2, 3, 4, 5, 6, 7, 8, 9].map(n => <option key={n} value={n}>让{n}子</option>)}
+                    </select>
+                    {handicapStones >= 2 && (
+                        <label className="checkbox-label">
+                            <input type="checkbox" checked={freeHandicap} onChange={e => setFreeHandicap(e.target.checked)} />
+                            自由放置
+                        </label>
+                    )}
+                    <select value={rules} onChange={e => {
+                        const r = e.target.value as Rules;
+                        setRules(r);
+                        setKomi(handicapStones >= 2 ? 0.5 : DEFAULT_KOMI[r]);
+                    }}>
+                        <option value="Chinese">中国规则</option>
+                        <option value="Japanese">日本规则</option>
+                        <option value="Aga">AGA 规则</option>
+                        <option value="NewZealand">新西兰规则</option>
+                        <option value="TrompTaylor">Tromp-Taylor 规则</option>
+                    </select>
+                </div>
+            )}
+            {gameType === "Gomoku" && (
+                <div className="go-settings">
+                    <select value={gomokuRule} onChange={e => setGomokuRule(e.target.value as GomokuRule)}>
+                        <option value="Freestyle">无限制 (长连也胜)</option>
+                        <option value="Standard">标准 (恰好五连)</option>
+                        <option value="Caro">Caro (两端被堵不算)</option>
+                    </select>
+                </div>
+            )}
+            {(gameType === "Gomoku" || gameType === "Renju") && (
+                <div className="go-settings">
+                    <select value={openingRule ?? ""} onChange={e => setOpeningRule((e.target.value || null) as OpeningRule | null)}>
+                        <option value="">无开局规则</option>
+                        <option value="Swap">Swap 开局</option>
+                        <option value="Swap2">Swap2 开局</option>
+                        <option value="Soosorv8">Soosõrv-8 开局</option>
+                        <option value="Taraguchi10">Taraguchi-10 开局</option>
+                    </select>
+                </div>
+            )}
+
             <div className="action-buttons">
                 <button onClick={() => startNewGame(gameType)} className="restart-btn">重新开始</button>
-                <button onClick={handleResign} className="resign-btn">认输</button>
-                <button onClick={handleOfferDraw} className="draw-btn">求和</button>
+                {gameType === "Go" && <button onClick={handlePass} className="pass-btn">停一手</button>}
+                {!review && (
+                    <>
+                        <button onClick={handleUndo} disabled={!gameState.can_undo} className="undo-btn">悔棋</button>
+                        {!isOnline && <button onClick={handleRedo} disabled={!gameState.can_redo} className="redo-btn">重做</button>}
+                        <button onClick={handleResign} className="resign-btn">认输</button>
+                        <button onClick={handleOfferDraw} className="draw-btn">求和</button>
+                    </>
+                )}
+                <button onClick={handleExportSgf} className="sgf-btn">保存棋谱</button>
+                {!isOnline && <button onClick={handleLoadSgf} className="sgf-btn">打开棋谱</button>}
+                {!isOnline && !review && <button onClick={handleStartReview} className="review-btn">复盘</button>}
             </div>
 
+            {review && (
+                <div className="review-controls">
+                    <p>复盘: 第 {review.move_number} 手</p>
+                    <div className="action-buttons">
+                        <button onClick={() => handleNavigate("start")}>⏮</button>
+                        <button onClick={() => handleNavigate("back")}>◀</button>
+                        <button onClick={() => handleNavigate("forward")}>▶</button>
+                        <button onClick={() => handleNavigate("end")}>⏭</button>
+                    </div>
+                    {review.variations > 1 && (
+                        <div className="action-buttons">
+                            <button disabled={review.variation === 0} onClick={() => handleSwitchVariation(review.variation - 1)}>上一变化</button>
+                            <span>变化 {review.variation + 1}/{review.variations}</span>
+                            <button disabled={review.variation + 1 >= review.variations} onClick={() => handleSwitchVariation(review.variation + 1)}>下一变化</button>
+                        </div>
+                    )}
+                    {review.next_moves.length > 1 && <p>后续有 {review.next_moves.length} 个分支</p>}
+                    <div className="action-buttons">
+                        <button disabled={review.move_number === 0 && review.variations <= 1} onClick={() => reviewCommand("delete_variation")}>删除此变化</button>
+                        <button disabled={review.on_main_line} onClick={() => reviewCommand("promote_variation")}>设为主线</button>
+                    </div>
+                    <div className="markup-tools">
+                        {(["Stone", "Triangle", "Circle", "Square", "Cross", "Label"] as MarkupTool[]).map(tool => (
+                            <button key={tool} className={markupTool === tool ? "active" : ""} onClick={() => setMarkupTool(tool)}>
+                                {{ Stone: "落子", Triangle: "△", Circle: "○", Square: "□", Cross: "✕", Label: "A" }[tool]}
+                            </button>
+                        ))}
+                    </div>
+                    <textarea
+                        className="review-comment"
+                        value={commentDraft}
+                        placeholder="评论"
+                        onChange={e => setCommentDraft(e.target.value)}
+                        onBlur={() => commentDraft !== review.comment && reviewCommand("set_review_comment", { comment: commentDraft })}
+                    />
+                    <button onClick={handleCloseReview}>退出复盘</button>
+                </div>
+            )}
+
             <div className="status-box">
                 <p>{status}</p>
                 <p>当前回合: {gameState.current_turn === "Black" ? "黑方" : "白方"}</p>
+                {gameState.phase === "PlacingHandicap" && <p>黑方放置让子, 剩余 {gameState.handicap_remaining} 子</p>}
+                {gameType === "Go" && <p>提子: 黑 {gameState.prisoners.black} / 白 {gameState.prisoners.white}</p>}
+                {gameType === "Pente" && <p>吃对: 黑 {gameState.prisoners.black / 2} / 白 {gameState.prisoners.white / 2} (吃满 5 对获胜)</p>}
+                {gameType === "AtariGo" && !gameState.winner && <p>先吃掉对方棋子的一方获胜</p>}
+                {gameType === "Connect6" && !gameState.winner && <p>本回合还需落 {gameState.stones_to_place - (pendingStone ? 1 : 0)} 子</p>}
             </div>
 
+            {gameState.opening && gameState.opening.step && !gameState.winner && !gameState.is_draw && (
+                <OpeningControls
+                    opening={gameState.opening}
+                    stones={gameState.board.flat().filter(cell => cell !== "None").length}
+                    offerCount={offerCount}
+                    setOfferCount={setOfferCount}
+                    onChoose={handleOpeningChoice}
+                />
+            )}
+
+            {gameState.phase === "Scoring" && !gameState.winner && !gameState.is_draw && gameState.score && (
+                <div className="scoring-controls">
+                    <p>点击棋子标记死子</p>
+                    <ScoreTable score={gameState.score} />
+                    <div className="action-buttons">
+                        <button onClick={handleAcceptScore}>确认结果</button>
+                        <button onClick={handleResumePlay}>继续对局</button>
+                    </div>
+                </div>
+            )}
+
             <div className="network-controls">
                 <h3>网络对战</h3>
                 <input value={port} onChange={e => setPort(e.target.value)} placeholder="端口" />
@@ -295,7 +950,64 @@ function App() {
                         <input type="checkbox" checked={isAiMode} onChange={e => setIsAiMode(e.target.checked)} />
                         启用 AI 对手
                     </label>
-                    {isAiMode && <button onClick={handleAI} className="ai-act-btn">AI 立即行动</button>}
+                    {isAiMode && (
+                        <select value={difficulty} onChange={e => updateDifficulty(e.target.value as Difficulty)}>
+                            <option value="Beginner">初学</option>
+                            <option value="Easy">简单</option>
+                            <option value="Medium">中等</option>
+                            <option value="Hard">困难</option>
+                            <option value="Strong">强</option>
+                        </select>
+                    )}
+                    {isAiMode && (gameType === "Go" || gameType === "AtariGo") && (
+                        <div className="go-settings">
+                            <label>
+                                模拟次数
+                                <input type="number" min={100} step={100} value={aiConfig.playouts ?? ""} placeholder="按难度"
+                                    onChange={e => {
+                                        const playouts = parseInt(e.target.value);
+                                        updateAiConfig({ ...aiConfig, playouts: playouts > 0 ? playouts : null });
+                                    }} />
+                            </label>
+                            <label>
+                                限时(秒)
+                                <input type="number" min={0} step={1} value={aiConfig.time_limit_ms === null ? "" : aiConfig.time_limit_ms / 1000} placeholder="按难度"
+                                    onChange={e => {
+                                        const seconds = parseFloat(e.target.value);
+                                        updateAiConfig({ ...aiConfig, time_limit_ms: seconds > 0 ? Math.round(seconds * 1000) : null });
+                                    }} />
+                            </label>
+                        </div>
+                    )}
+                    {isAiMode && gameType !== "Connect6" && gameType !== "Pente" && gameType !== "AtariGo" && (
+                        <div className="external-engine">
+                            {externalEngines[engineProtocol] ? (
+                                <>
+                                    <div className="ai-progress">外部引擎: {externalEngines[engineProtocol]}</div>
+                                    <button onClick={stopExternalEngine} disabled={aiThinking}>使用内置 AI</button>
+                                </>
+                            ) : (
+                                <>
+                                    <input value={engineCommands[engineProtocol]}
+                                        onChange={e => setEngineCommands(commands => ({ ...commands, [engineProtocol]: e.target.value }))}
+                                        placeholder={gameType === "Go" ? "GTP 引擎命令" : "Gomocup 引擎命令"} />
+                                    <button onClick={startExternalEngine} disabled={aiThinking}>
+                                        {gameType === "Go" ? "连接 GTP 引擎" : "连接 Gomocup 引擎"}
+                                    </button>
+                                </>
+                            )}
+                        </div>
+                    )}
+                    {isAiMode && <button onClick={() => handleAI()} disabled={aiThinking} className="ai-act-btn">AI 立即行动</button>}
+                    {aiThinking && (
+                        <div className="ai-thinking">
+                            {aiProgress && <div className="ai-progress">{describeProgress(aiProgress)}</div>}
+                            <div className="action-buttons">
+                                <button onClick={() => cancelAI(true)}>立即落子</button>
+                                <button onClick={() => cancelAI(false)}>取消</button>
+                            </div>
+                        </div>
+                    )}
                 </div>
             </div>
         </div>
@@ -309,7 +1021,14 @@ function App() {
         }}>
           {gameState.board.map((row, y) =>
             row.map((cell, x) => {
-                const isLastMove = gameState.last_move && gameState.last_move[0] === x && gameState.last_move[1] === y;
+                const lastMove = gameState.last_move;
+                const isLastMove = lastMove !== null && lastMove !== "Pass" && lastMove.Place[0] === x && lastMove.Place[1] === y;
+                const isCaptured = cell === "None" && gameState.captured.some(([cx, cy]) => cx === x && cy === y);
+                const isDead = gameState.dead_stones.some(([dx, dy]) => dx === x && dy === y);
+                const owner = gameState.territory ? gameState.territory[y][x] : "None";
+                const mark = review?.markup.find(m => m.point[0] === x && m.point[1] === y);
+                const isOffered = gameState.opening?.offers.some(([ox, oy]) => ox === x && oy === y) ?? false;
+                const isPending = pendingStone !== null && pendingStone[0] === x && pendingStone[1] === y;
                 return (
                   <div
                     key={`${x}-${y}`}
@@ -319,10 +1038,24 @@ function App() {
                     <div className="grid-line horizontal"></div>
                     <div className="grid-line vertical"></div>
                     {cell !== "None" && (
-                      <div className={`stone ${cell.toLowerCase()} ${isLastMove ? 'last-move' : ''}`}>
+                      <div className={`stone ${cell.toLowerCase()} ${isLastMove ? 'last-move' : ''} ${isDead ? 'dead' : ''}`}>
                           {isLastMove && <div className="marker"></div>}
                       </div>
                     )}
+                    {isCaptured && (
+                      // Captured stones are always the color that is now to move
+                      <div key={`captured-${gameState.prisoners.black}-${gameState.prisoners.white}`} className={`stone ${gameState.current_turn.toLowerCase()} captured`}></div>
+                    )}
+                    {isOffered && <div className="offer-marker"></div>}
+                    {isPending && <div className={`stone ${gameState.current_turn.toLowerCase()} pending`}></div>}
+                    {owner !== "None" && (cell === "None" || isDead) && (
+                      <div className={`territory ${owner.toLowerCase()}`}></div>
+                    )}
+                    {mark && (
+                      <div className={`markup ${typeof mark.kind === "object" ? "label" : mark.kind.toLowerCase()} ${cell !== "None" ? `on-${cell.toLowerCase()}` : ''}`}>
+                          {typeof mark.kind === "object" ? mark.kind.Label : { Triangle: "△", Circle: "○", Square: "□", Cross: "✕" }[mark.kind]}
+                      </div>
+                    )}
                     {/* Star points (Hoshi) */}
                     {isStarPoint(x, y, gameState.board.length) && <div className="star-point"></div>}
                   </div>
@@ -335,7 +1068,77 @@ function App() {
   );
 }
 
+const SEAT_NAMES: Record<Seat, string> = { First: "先手方", Second: "后手方" };
+
+const OPENING_STEPS: Record<OpeningStep, string> = {
+  Place: "落子",
+  Choose: "选择执黑或执白",
+  DeclareOffers: "指定第五手打点数",
+  Offer: "提出第五手打点",
+  Pick: "从打点中选定第五手",
+};
+
+function OpeningControls({ opening, stones, offerCount, setOfferCount, onChoose }: {
+  opening: Opening;
+  stones: number;
+  offerCount: number;
+  setOfferCount: (count: number) => void;
+  onChoose: (choice: OpeningChoice) => void;
+}) {
+  const step = opening.step!;
+  return (
+    <div className="opening-controls">
+      <p>开局: {SEAT_NAMES[opening.actor]}{OPENING_STEPS[step]}</p>
+      <p>执黑: {SEAT_NAMES[opening.black]}</p>
+      {step === "Offer" && <p>已提出 {opening.offers.length}/{opening.offer_count} 个打点</p>}
+      {step === "Choose" && (
+        <div className="action-buttons">
+          <button onClick={() => onChoose("Black")}>执黑</button>
+          <button onClick={() => onChoose("White")}>执白</button>
+          {opening.rule === "Swap2" && stones === 3 && <button onClick={() => onChoose("PlaceTwo")}>再放两子</button>}
+        </div>
+      )}
+      {step === "DeclareOffers" && (
+        <div className="action-buttons">
+          <select value={offerCount} onChange={e => setOfferCount(parseInt(e.target.value))}>
+            {[1, 2, 3, 4, 5, 6, 7, 8].map(n => <option key={n} value={n}>{n} 个打点</option>)}
+          </select>
+          <button onClick={() => onChoose({ Offers: offerCount })}>确定</button>
+        </div>
+      )}
+    </div>
+  );
+}
+
+function ScoreTable({ score }: { score: Score }) {
+    const counted = score.method === "Area" ? "stones" : "captures";
+    return (
+        <table className="score-table">
+            <thead>
+                <tr><th></th><th>黑方</th><th>白方</th></tr>
+            </thead>
+            <tbody>
+                <tr><td>地</td><td>{score.black.territory}</td><td>{score.white.territory}</td></tr>
+                <tr><td>{counted === "stones" ? "子" : "提子"}</td><td>{score.black[counted]}</td><td>{score.white[counted]}</td></tr>
+                <tr><td>贴目</td><td>{score.black.komi}</td><td>{score.white.komi}</td></tr>
+                {score.white.handicap > 0 && <tr><td>让子补偿</td><td>0</td><td>{score.white.handicap}</td></tr>}
+                <tr><td>合计</td><td>{score.black.total}</td><td>{score.white.total}</td></tr>
+            </tbody>
+            <tfoot>
+                <tr><td colSpan={3}>{score.result}</td></tr>
+            </tfoot>
+        </table>
+    );
+}
+
 function isStarPoint(x: number, y: number, size: number) {
+    if (size === 9) {
+        return ([2, 6].includes(x) && [2, 6].includes(y)) || (x === 4 && y === 4);
+    }
+    if (size === 13) {
+        const points = [3, 6, 9];
+        return points.includes(x) && points.includes(y);
+    }
     if (size === 19) {
         const points = [3, 9, 15];
         return points.includes(x) && points.includes(y);